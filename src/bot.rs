@@ -1,5 +1,7 @@
+use access::{AccessList, Role, callback_not_allowed, not_allowed, permission_denied};
 use callback_query_handlers::callback_query_handler;
 use command_handlers::*;
+use rate_limit::{RateLimiter, slow_down};
 use teloxide::{
     dispatching::{
         UpdateFilterExt, UpdateHandler,
@@ -8,11 +10,33 @@ use teloxide::{
     dptree::case,
     filter_command,
     macros::BotCommands,
+    prelude::*,
     types::Update,
 };
+use vote_skip::SkipVotes;
 
+pub mod access;
+pub mod album_art;
+mod browse_view;
+mod callback_data;
 mod callback_query_handlers;
+pub mod callback_state;
 mod command_handlers;
+mod ls_view;
+mod modes;
+pub mod now_playing;
+mod playlists;
+mod presenter;
+mod queue_view;
+pub mod rate_limit;
+mod rich_text;
+mod search_query;
+mod search_view;
+mod seek;
+mod song_actions;
+pub mod subscriptions;
+mod volume;
+pub mod vote_skip;
 
 #[derive(BotCommands, Clone)]
 #[command(
@@ -30,14 +54,18 @@ enum Commands {
     Next,
     #[command(description = "Switch to previous track")]
     Prev,
-    #[command(description = "Show information about current song", aliases=["np"])]
-    Current,
+    #[command(description = "Show information about current song, /np live keeps it updated", aliases=["np"])]
+    Current(String),
     #[command(description = "Show songs in the queue", aliases=["q"])]
     Queue,
     #[command(description = "Add a song from youtube", aliases=["yt"])]
     AddYt,
-    #[command(description = "Search in the db", aliases=["s"])]
+    #[command(description = "Search in the db (artist:, album:, title:, year:, genre:)", aliases=["s"])]
     Search(String),
+    #[command(description = "Browse the library by artist and album")]
+    Browse,
+    #[command(description = "List a directory of the music folder")]
+    Ls(String),
     #[command(description = "Add random songs", aliases=["rand"])]
     AddRand(String),
     #[command(description = "Add all songs to queue", aliases=["all"])]
@@ -50,6 +78,93 @@ enum Commands {
     Shuffle,
     #[command(description = "Show DB stats")]
     Stats,
+    #[command(description = "Set the volume (0-100, +N, -N) or show volume buttons", aliases=["vol"])]
+    Volume(String),
+    #[command(description = "Seek in the current song (1:30, +30, -15, 50%)")]
+    Seek(String),
+    #[command(description = "Show and toggle repeat, random, single, consume and crossfade")]
+    Modes,
+    #[command(description = "Stop playback")]
+    Stop,
+    #[command(description = "Play the song at a position in the queue")]
+    PlayPos(String),
+    #[command(description = "Play the song with an id in the queue")]
+    PlayId(String),
+    #[command(description = "Remove a song or a range of songs (3-5) from the queue", aliases=["rm"])]
+    Remove(String),
+    #[command(description = "Move a song to another position in the queue", aliases=["mv"])]
+    Move(String),
+    #[command(description = "Show saved playlists", aliases=["pls"])]
+    Playlists,
+    #[command(description = "Show the songs of a saved playlist")]
+    PlaylistShow(String),
+    #[command(description = "Save the queue as a playlist")]
+    Save(String),
+    #[command(description = "Add a saved playlist to the queue")]
+    Load(String),
+    #[command(description = "Delete a saved playlist")]
+    RmPlaylist(String),
+    #[command(description = "Rename a saved playlist (old | new)")]
+    RenamePlaylist(String),
+    #[command(description = "Post song changes in this chat (silent for no sound)")]
+    Subscribe(String),
+    #[command(description = "Stop posting song changes in this chat")]
+    Unsubscribe(String),
+}
+
+impl Commands {
+    /// Lowest role allowed to run the command
+    fn required_role(&self) -> Role {
+        match self {
+            Commands::Clear | Commands::AddAll | Commands::RmPlaylist(_) => Role::Admin,
+            Commands::Play
+            | Commands::Next
+            | Commands::Prev
+            | Commands::AddYt
+            | Commands::AddRand(_)
+            | Commands::AddFile
+            | Commands::Shuffle
+            | Commands::Volume(_)
+            | Commands::Seek(_)
+            | Commands::Stop
+            | Commands::PlayPos(_)
+            | Commands::PlayId(_)
+            | Commands::Remove(_)
+            | Commands::Move(_)
+            | Commands::Save(_)
+            | Commands::Load(_)
+            | Commands::RenamePlaylist(_)
+            | Commands::Subscribe(_)
+            | Commands::Unsubscribe(_) => Role::Dj,
+            Commands::Start
+            | Commands::Help
+            | Commands::Current(_)
+            | Commands::Queue
+            | Commands::Search(_)
+            | Commands::Browse
+            | Commands::Ls(_)
+            | Commands::Stats
+            | Commands::Modes
+            | Commands::Playlists
+            | Commands::PlaylistShow(_) => Role::Listener,
+        }
+    }
+
+    /// How many songs the command adds to the queue, which it's charged for
+    /// by the rate limit. `None` for commands that don't add songs.
+    fn rate_cost(&self) -> Option<u32> {
+        match self {
+            // An invalid amount is refused by the handler, charge it like one song.
+            // Amounts past u32 cost a full bucket anyway.
+            Commands::AddRand(amount) => Some(
+                rand_amount(amount)
+                    .map_or(1, |x| u32::try_from(x).unwrap_or(u32::MAX))
+                    .max(1),
+            ),
+            Commands::AddYt | Commands::AddFile => Some(1),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -59,23 +174,69 @@ pub enum BotState {
 }
 pub fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
     let cmd_handler = filter_command::<Commands, _>()
+        .branch(
+            dptree::filter(|cmd: Commands, role: Role| role < cmd.required_role())
+                .endpoint(permission_denied),
+        )
+        .branch(
+            dptree::filter_map(|cmd: Commands, role: Role| {
+                cmd.rate_cost().filter(|_| role < Role::Admin)
+            })
+            .filter_map(|msg: Message, limiter: RateLimiter, cost: u32| {
+                limiter.check(msg.from?.id, cost).err()
+            })
+            .endpoint(slow_down),
+        )
         .branch(case![Commands::Start].endpoint(start))
         .branch(case![Commands::Help].endpoint(help))
         .branch(case![Commands::Play].endpoint(play))
         .branch(case![Commands::Next].endpoint(next))
         .branch(case![Commands::Prev].endpoint(prev))
-        .branch(case![Commands::Current].endpoint(curr))
+        .branch(case![Commands::Current(mode)].endpoint(curr))
         .branch(case![Commands::Queue].endpoint(queue))
         .branch(case![Commands::Stats].endpoint(stats))
         .branch(case![Commands::Clear].endpoint(clear))
         .branch(case![Commands::Search(query)].endpoint(search))
+        .branch(case![Commands::Browse].endpoint(browse))
+        .branch(case![Commands::Ls(path)].endpoint(ls))
         .branch(case![Commands::AddRand(amount)].endpoint(add_rand))
         .branch(case![Commands::AddAll].endpoint(add_all))
         .branch(case![Commands::Shuffle].endpoint(shuffle))
         .branch(case![Commands::AddFile].endpoint(add_file))
-        .branch(case![Commands::AddYt].endpoint(add_yt));
-    let msg_handler = Update::filter_message().branch(cmd_handler);
-    let callback_query_handler = Update::filter_callback_query().endpoint(callback_query_handler);
+        .branch(case![Commands::AddYt].endpoint(add_yt))
+        .branch(case![Commands::Volume(change)].endpoint(volume))
+        .branch(case![Commands::Seek(target)].endpoint(seek))
+        .branch(case![Commands::Modes].endpoint(modes))
+        .branch(case![Commands::Stop].endpoint(stop))
+        .branch(case![Commands::PlayPos(pos)].endpoint(play_pos))
+        .branch(case![Commands::PlayId(id)].endpoint(play_id))
+        .branch(case![Commands::Remove(range)].endpoint(remove))
+        .branch(case![Commands::Move(args)].endpoint(move_song))
+        .branch(case![Commands::Playlists].endpoint(playlists))
+        .branch(case![Commands::PlaylistShow(name)].endpoint(playlist_show))
+        .branch(case![Commands::Save(name)].endpoint(save_playlist))
+        .branch(case![Commands::Load(name)].endpoint(load_playlist))
+        .branch(case![Commands::RmPlaylist(name)].endpoint(remove_playlist))
+        .branch(case![Commands::RenamePlaylist(args)].endpoint(rename_playlist))
+        .branch(case![Commands::Subscribe(args)].endpoint(subscribe))
+        .branch(case![Commands::Unsubscribe(args)].endpoint(unsubscribe));
+    let msg_handler = Update::filter_message()
+        .branch(
+            dptree::filter_map(|msg: Message, acl: AccessList| acl.message_role(&msg))
+                .inspect(|msg: Message, votes: SkipVotes| {
+                    if let Some(user) = &msg.from {
+                        votes.seen(msg.chat.id, user.id);
+                    }
+                })
+                .chain(cmd_handler),
+        )
+        .branch(filter_command::<Commands, _>().endpoint(not_allowed));
+    let callback_query_handler = Update::filter_callback_query()
+        .branch(
+            dptree::filter_map(|q: CallbackQuery, acl: AccessList| acl.callback_role(&q))
+                .endpoint(callback_query_handler),
+        )
+        .endpoint(callback_not_allowed);
     dialogue::enter::<Update, InMemStorage<BotState>, BotState, _>()
         .branch(msg_handler)
         .branch(callback_query_handler)
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use log::{info, warn};
+use teloxide::{
+    prelude::*,
+    types::{ReplyParameters, User},
+};
+
+use super::Commands;
+
+type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// What a user is allowed to do, ordered from least to most privileged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Can look at the queue, the current song and search results
+    Listener,
+    /// Can control playback and add songs
+    Dj,
+    /// Can do everything, including wiping the queue
+    Admin,
+}
+
+impl Role {
+    /// Who holds this role or a higher one, for denial messages
+    pub fn holders(self) -> &'static str {
+        match self {
+            Role::Listener => "allowed users",
+            Role::Dj => "DJs and admins",
+            Role::Admin => "admins",
+        }
+    }
+}
+
+/// Users and chats allowed to talk to the bot, read from the environment as
+/// comma separated ids in `TMPC_ADMINS`, `TMPC_DJS` and `TMPC_LISTENERS`.
+///
+/// A group chat id grants its role to everyone in that group. When nothing is
+/// configured, the bot is open and everyone is an admin.
+#[derive(Clone, Default)]
+pub struct AccessList {
+    roles: Arc<HashMap<i64, Role>>,
+}
+
+impl AccessList {
+    pub fn from_env() -> Self {
+        let mut roles = HashMap::new();
+        // Lower roles first so that an id listed twice keeps the higher one
+        for (var, role) in [
+            ("TMPC_LISTENERS", Role::Listener),
+            ("TMPC_DJS", Role::Dj),
+            ("TMPC_ADMINS", Role::Admin),
+        ] {
+            let Ok(ids) = env::var(var) else {
+                continue;
+            };
+            for id in ids.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                match id.parse::<i64>() {
+                    Ok(id) => {
+                        roles.insert(id, role);
+                    }
+                    Err(_) => warn!("Ignoring invalid id in {var}: {id}"),
+                }
+            }
+        }
+        if roles.is_empty() {
+            warn!("No access list configured, everyone can use every command");
+        } else {
+            info!("Loaded {} access list entries", roles.len());
+        }
+
+        Self {
+            roles: Arc::new(roles),
+        }
+    }
+
+    /// Highest role granted to the user, either directly or through the chat
+    /// they're writing from. `None` means they aren't allowed at all
+    pub fn role(&self, user: Option<&User>, chat: ChatId) -> Option<Role> {
+        if self.roles.is_empty() {
+            return Some(Role::Admin);
+        }
+        let by_user = user.and_then(|x| self.roles.get(&(x.id.0 as i64)));
+        let by_chat = self.roles.get(&chat.0);
+        by_user.max(by_chat).copied()
+    }
+
+    pub fn message_role(&self, msg: &Message) -> Option<Role> {
+        self.role(msg.from.as_ref(), msg.chat.id)
+    }
+
+    pub fn callback_role(&self, q: &CallbackQuery) -> Option<Role> {
+        let chat = q.message.as_ref().map(|x| x.chat().id);
+        self.role(Some(&q.from), chat.unwrap_or(ChatId(q.from.id.0 as i64)))
+    }
+}
+
+/// Reply to commands from people who aren't on the access list
+pub async fn not_allowed(bot: Bot, msg: Message) -> HandlerResult {
+    info!("Refused command from unknown user in chat {}", msg.chat.id);
+    bot.send_message(msg.chat.id, "🔒 Sorry, you're not allowed to use this bot")
+        .reply_parameters(ReplyParameters {
+            message_id: msg.id,
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
+
+/// Answer buttons tapped by people who aren't on the access list, so they
+/// don't keep spinning
+pub async fn callback_not_allowed(bot: Bot, q: CallbackQuery) -> HandlerResult {
+    info!("Refused button from unknown user {}", q.from.id);
+    bot.answer_callback_query(q.id)
+        .text("🔒 Sorry, you're not allowed to use this bot")
+        .await?;
+    Ok(())
+}
+
+/// Reply to commands that need a higher role than the user has
+pub(super) async fn permission_denied(bot: Bot, msg: Message, cmd: Commands) -> HandlerResult {
+    let text = format!(
+        "🔒 Sorry, only {} can do that",
+        cmd.required_role().holders()
+    );
+    bot.send_message(msg.chat.id, text)
+        .reply_parameters(ReplyParameters {
+            message_id: msg.id,
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::{error::Error, path::PathBuf};
+
+use log::{debug, info, warn};
+use teloxide::{
+    prelude::*,
+    types::{FileId, InputFile, MessageId, ParseMode, ReplyParameters},
+};
+
+use crate::connection::{MpdPool, quote};
+
+/// Cover files looked for next to a song when the music folder is readable
+pub const COVER_FILES: [&str; 4] = ["cover.jpg", "cover.png", "folder.jpg", "front.jpg"];
+
+/// Telegram file ids of covers already sent, per album directory for cover
+/// files and per song for pictures embedded in it
+#[derive(Clone)]
+pub struct AlbumArt {
+    tree: sled::Tree,
+}
+
+impl AlbumArt {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("album_art")?,
+        })
+    }
+
+    /// Send the HTML `caption` with the cover of `file` as a photo, or as
+    /// text when there's no cover
+    pub async fn send(
+        &self,
+        bot: &Bot,
+        mpd: &MpdPool,
+        chat: ChatId,
+        reply_to: Option<MessageId>,
+        file: &str,
+        caption: String,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let reply = reply_to.map(|message_id| ReplyParameters {
+            message_id,
+            ..Default::default()
+        });
+        // A song's own picture wins over the cover of its directory
+        let mut cached = None;
+        for key in [file, directory(file)] {
+            if let Some(id) = self.tree.get(key)? {
+                cached = Some((key, id));
+                break;
+            }
+        }
+        if let Some((key, id)) = cached {
+            let id = String::from_utf8_lossy(&id).to_string();
+            let mut req = bot
+                .send_photo(chat, InputFile::file_id(FileId(id)))
+                .caption(&caption)
+                .parse_mode(ParseMode::Html);
+            if let Some(reply) = reply.clone() {
+                req = req.reply_parameters(reply);
+            }
+            match req.await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    warn!("Cached cover of {key} didn't work: {}", e);
+                    self.tree.remove(key)?;
+                }
+            }
+        }
+
+        let Some((cover, embedded)) = fetch(mpd, file).await else {
+            let mut req = bot.send_message(chat, caption).parse_mode(ParseMode::Html);
+            if let Some(reply) = reply {
+                req = req.reply_parameters(reply);
+            }
+            req.await?;
+            return Ok(());
+        };
+        let mut req = bot
+            .send_photo(chat, InputFile::memory(cover))
+            .caption(caption)
+            .parse_mode(ParseMode::Html);
+        if let Some(reply) = reply {
+            req = req.reply_parameters(reply);
+        }
+        let sent = req.await?;
+        if let Some(photo) = sent.photo().and_then(|x| x.last()) {
+            let key = if embedded { file } else { directory(file) };
+            info!("Cached cover of {key}");
+            self.tree.insert(key, photo.file.id.0.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory of a song, which MPD's `albumart` looks for covers in
+fn directory(file: &str) -> &str {
+    file.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Cover of a song from MPD: a cover file through `albumart`, then a picture
+/// embedded in the song through `readpicture`, then a cover file read
+/// straight from the music folder. Also tells whether the picture is embedded,
+/// which only applies to that one song.
+async fn fetch(mpd: &MpdPool, file: &str) -> Option<(Vec<u8>, bool)> {
+    let arg = quote(file).ok()?;
+    for (command, embedded) in [("albumart", false), ("readpicture", true)] {
+        match mpd.binary_command(format!("{command} {arg}")).await {
+            Ok(Some(cover)) if !cover.is_empty() => return Some((cover, embedded)),
+            Ok(_) => {}
+            Err(e) => debug!("No cover from {command} for {file}: {}", e),
+        }
+    }
+
+    let storages = mpd.run(|conn| conn.mounts()).await.ok()?;
+    for storage in storages.into_iter().map(|x| PathBuf::from(x.storage)) {
+        for name in COVER_FILES {
+            let path = storage.join(directory(file)).join(name);
+            if let Ok(cover) = tokio::fs::read(&path).await {
+                return Some((cover, false));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+
+use mpd::{Query, Term, error::Error as MpdError};
+use teloxide::types::{ChatId, InlineKeyboardMarkup, MessageId};
+
+use super::{
+    callback_data::{CallbackAction, page_row},
+    callback_state::{CallbackState, CallbackStore, VIEW_SLOT},
+    presenter,
+};
+use crate::connection::{MpdClient, MpdPool};
+
+pub const BROWSE_PAGE_SIZE: usize = 10;
+
+/// The level above a `/browse` level, `None` at the top
+pub fn parent(level: &CallbackState) -> Option<CallbackState> {
+    match level {
+        CallbackState::Artist { .. } => Some(CallbackState::Artists),
+        CallbackState::Album { artist, .. } => Some(CallbackState::Artist {
+            name: artist.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Add every song of an artist or album to the queue, returning its name
+pub fn add_level(conn: &mut MpdClient, level: &CallbackState) -> Result<Option<String>, MpdError> {
+    let mut query = Query::new();
+    let name = match level {
+        CallbackState::Artist { name } => {
+            query.and(Term::Tag("Artist".into()), name.as_str());
+            name.clone()
+        }
+        CallbackState::Album { artist, album } => {
+            query
+                .and(Term::Tag("Artist".into()), artist.as_str())
+                .and(Term::Tag("Album".into()), album.as_str());
+            album.clone()
+        }
+        _ => return Ok(None),
+    };
+    conn.findadd(&query)?;
+    Ok(Some(name))
+}
+
+/// Render one page of a `/browse` level with its keyboard, replacing what the
+/// message stored before with the level and the entries of the page
+pub async fn browse_page(
+    mpd: &MpdPool,
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+    level: CallbackState,
+    page: u32,
+) -> Result<(String, InlineKeyboardMarkup), Box<dyn Error + Send + Sync>> {
+    let query_level = level.clone();
+    let entries = mpd.run(move |conn| entries(conn, &query_level)).await?;
+    let pages = entries.len().div_ceil(BROWSE_PAGE_SIZE).max(1) as u32;
+    let page = page.min(pages - 1);
+    let start = page as usize * BROWSE_PAGE_SIZE;
+
+    store.clear(chat, msg)?;
+    store.put(chat, msg, VIEW_SLOT, &level)?;
+    let mut buttons = vec![];
+    for (slot, (text, entry)) in
+        (VIEW_SLOT + 1..).zip(entries.iter().skip(start).take(BROWSE_PAGE_SIZE))
+    {
+        store.put(chat, msg, slot, entry)?;
+        buttons.push(match entry {
+            CallbackState::Song { .. } => vec![CallbackAction::SearchResult { slot }.button(text)],
+            _ => vec![
+                CallbackAction::BrowseOpen { slot }.button(text),
+                CallbackAction::BrowseAdd { slot }.button("➕"),
+            ],
+        });
+    }
+    buttons.extend(page_row(page, pages, |page| CallbackAction::BrowsePage {
+        page,
+    }));
+
+    let add = CallbackAction::BrowseAdd { slot: VIEW_SLOT };
+    let count = entries.len();
+    let text = match &level {
+        CallbackState::Artist { name } => {
+            buttons.push(vec![
+                CallbackAction::BrowseUp.button("⬆ Artists"),
+                add.button("➕ Add all"),
+            ]);
+            format!("👤 {name}\n💿 {count} albums, page {}/{pages}", page + 1)
+        }
+        CallbackState::Album { artist, album } => {
+            buttons.push(vec![
+                CallbackAction::BrowseUp.button(format!("⬆ {artist}")),
+                add.button("➕ Add album"),
+            ]);
+            format!(
+                "💿 {album}\n👤 {artist}\n🎵 {count} tracks, page {}/{pages}",
+                page + 1
+            )
+        }
+        _ => format!("🎤 {count} artists, page {}/{pages}", page + 1),
+    };
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
+
+/// Button text and state of every entry of a level
+fn entries(
+    conn: &mut MpdClient,
+    level: &CallbackState,
+) -> Result<Vec<(String, CallbackState)>, MpdError> {
+    Ok(match level {
+        CallbackState::Artist { name } => conn
+            .list(
+                &Term::Tag("Album".into()),
+                Query::new().and(Term::Tag("Artist".into()), name.as_str()),
+            )?
+            .into_iter()
+            .map(|album| {
+                let text = if album.is_empty() {
+                    "💿 (no album)".into()
+                } else {
+                    format!("💿 {album}")
+                };
+                let artist = name.clone();
+                (text, CallbackState::Album { artist, album })
+            })
+            .collect(),
+        CallbackState::Album { artist, album } => conn
+            .find(
+                Query::new()
+                    .and(Term::Tag("Artist".into()), artist.as_str())
+                    .and(Term::Tag("Album".into()), album.as_str()),
+                None,
+            )?
+            .into_iter()
+            .map(|song| {
+                (
+                    format!("🎵 {}", presenter::title(&song)),
+                    CallbackState::Song { file: song.file },
+                )
+            })
+            .collect(),
+        _ => conn
+            .list(&Term::Tag("Artist".into()), &Query::new())?
+            .into_iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| (format!("👤 {name}"), CallbackState::Artist { name }))
+            .collect(),
+    })
+}
@@ -0,0 +1,394 @@
+use mpd::Id;
+use teloxide::types::InlineKeyboardButton;
+
+use super::{
+    modes::ModeToggle, now_playing::PlayerControl, song_actions::SongAction, volume::VolumeChange,
+};
+
+/// Telegram refuses callback data longer than this many bytes
+pub const MAX_CALLBACK_DATA: usize = 64;
+/// Prefix of every payload. Bump it when the format changes so buttons of
+/// older messages are rejected instead of misread.
+const VERSION: char = '1';
+
+/// What an inline button does, sent as its callback data.
+///
+/// Payloads are the version, a one letter code and `:` separated arguments,
+/// where only the last argument may contain a `:`. Search results are slots of
+/// the message's [`super::callback_state::CallbackStore`] entries.
+#[derive(Clone, Debug)]
+pub enum CallbackAction {
+    /// Open the action menu of a search result
+    SearchResult {
+        slot: u16,
+    },
+    /// Pick a stored playlist to add a search result to
+    PickPlaylist {
+        slot: u16,
+    },
+    /// Do something with a search result
+    Song {
+        slot: u16,
+        action: SongAction,
+    },
+    SearchPage {
+        page: u32,
+    },
+    AddAllResults,
+    VoteSkip {
+        song: Id,
+    },
+    ForceSkip {
+        song: Id,
+    },
+    Volume(VolumeChange),
+    ToggleMode(ModeToggle),
+    PlayId {
+        song: Id,
+    },
+    QueuePage {
+        page: u32,
+    },
+    QueuePlayNext {
+        song: Id,
+        page: u32,
+    },
+    QueueRemove {
+        song: Id,
+        page: u32,
+    },
+    LoadPlaylist {
+        name: String,
+    },
+    AppendPlaylist {
+        name: String,
+    },
+    /// Show the artist or album in `slot` of a `/browse` message
+    BrowseOpen {
+        slot: u16,
+    },
+    BrowsePage {
+        page: u32,
+    },
+    /// Go back to the level above
+    BrowseUp,
+    /// Add the artist or album in `slot` to the queue
+    BrowseAdd {
+        slot: u16,
+    },
+    /// Show the directory in `slot` of an `/ls` message
+    LsOpen {
+        slot: u16,
+    },
+    LsPage {
+        page: u32,
+    },
+    /// Go to the parent directory
+    LsUp,
+    /// Add the directory in `slot` to the queue
+    LsAdd {
+        slot: u16,
+    },
+    /// Controls of the live `/np live` message
+    Player(PlayerControl),
+}
+
+impl CallbackAction {
+    pub fn encode(&self) -> String {
+        let (code, args) = match self {
+            CallbackAction::SearchResult { slot } => ('i', slot.to_string()),
+            CallbackAction::PickPlaylist { slot } => ('S', slot.to_string()),
+            CallbackAction::Song { slot, action } => match action {
+                SongAction::PlayNext => ('N', slot.to_string()),
+                SongAction::Append => ('E', slot.to_string()),
+                SongAction::PlayNow => ('P', slot.to_string()),
+                SongAction::AddToPlaylist(name) => ('Y', format!("{slot}:{name}")),
+            },
+            CallbackAction::SearchPage { page } => ('g', page.to_string()),
+            CallbackAction::AddAllResults => ('a', String::new()),
+            CallbackAction::VoteSkip { song } => ('v', song.0.to_string()),
+            CallbackAction::ForceSkip { song } => ('f', song.0.to_string()),
+            CallbackAction::Volume(change) => match change {
+                VolumeChange::Set(x) => ('u', x.to_string()),
+                VolumeChange::By(x) => ('u', format!("{x:+}")),
+            },
+            CallbackAction::ToggleMode(toggle) => ('o', toggle.as_str().to_string()),
+            CallbackAction::PlayId { song } => ('p', song.0.to_string()),
+            CallbackAction::QueuePage { page } => ('q', page.to_string()),
+            CallbackAction::QueuePlayNext { song, page } => ('t', format!("{}:{page}", song.0)),
+            CallbackAction::QueueRemove { song, page } => ('x', format!("{}:{page}", song.0)),
+            CallbackAction::LoadPlaylist { name } => ('L', name.clone()),
+            CallbackAction::AppendPlaylist { name } => ('A', name.clone()),
+            CallbackAction::BrowseOpen { slot } => ('b', slot.to_string()),
+            CallbackAction::BrowsePage { page } => ('k', page.to_string()),
+            CallbackAction::BrowseUp => ('w', String::new()),
+            CallbackAction::BrowseAdd { slot } => ('d', slot.to_string()),
+            CallbackAction::LsOpen { slot } => ('l', slot.to_string()),
+            CallbackAction::LsPage { page } => ('h', page.to_string()),
+            CallbackAction::LsUp => ('U', String::new()),
+            CallbackAction::LsAdd { slot } => ('D', slot.to_string()),
+            CallbackAction::Player(control) => ('c', control.as_str().to_string()),
+        };
+        format!("{VERSION}{code}{args}")
+    }
+
+    /// `None` for payloads of another version or that don't make sense
+    pub fn parse(data: &str) -> Option<Self> {
+        let mut chars = data.strip_prefix(VERSION)?.chars();
+        let code = chars.next()?;
+        let args = chars.as_str();
+        let slot = || args.parse::<u16>().ok();
+        let page = || args.parse::<u32>().ok();
+        let song = || args.parse::<u32>().ok().map(Id);
+        let entry = || {
+            let (song, page) = args.split_once(':')?;
+            Some((Id(song.parse().ok()?), page.parse().ok()?))
+        };
+        let name = || (!args.is_empty()).then(|| args.to_string());
+        let song_action = |action| {
+            Some(CallbackAction::Song {
+                slot: slot()?,
+                action,
+            })
+        };
+        match code {
+            'i' => Some(CallbackAction::SearchResult { slot: slot()? }),
+            'S' => Some(CallbackAction::PickPlaylist { slot: slot()? }),
+            'N' => song_action(SongAction::PlayNext),
+            'E' => song_action(SongAction::Append),
+            'P' => song_action(SongAction::PlayNow),
+            'Y' => {
+                let (slot, name) = args.split_once(':')?;
+                if name.is_empty() {
+                    return None;
+                }
+                Some(CallbackAction::Song {
+                    slot: slot.parse().ok()?,
+                    action: SongAction::AddToPlaylist(name.to_string()),
+                })
+            }
+            'g' => Some(CallbackAction::SearchPage { page: page()? }),
+            'a' => args.is_empty().then_some(CallbackAction::AddAllResults),
+            'v' => Some(CallbackAction::VoteSkip { song: song()? }),
+            'f' => Some(CallbackAction::ForceSkip { song: song()? }),
+            'u' => Some(CallbackAction::Volume(VolumeChange::parse(args)?)),
+            'o' => Some(CallbackAction::ToggleMode(ModeToggle::parse(args)?)),
+            'p' => Some(CallbackAction::PlayId { song: song()? }),
+            'q' => Some(CallbackAction::QueuePage { page: page()? }),
+            't' => entry().map(|(song, page)| CallbackAction::QueuePlayNext { song, page }),
+            'x' => entry().map(|(song, page)| CallbackAction::QueueRemove { song, page }),
+            'L' => Some(CallbackAction::LoadPlaylist { name: name()? }),
+            'A' => Some(CallbackAction::AppendPlaylist { name: name()? }),
+            'b' => Some(CallbackAction::BrowseOpen { slot: slot()? }),
+            'k' => Some(CallbackAction::BrowsePage { page: page()? }),
+            'w' => args.is_empty().then_some(CallbackAction::BrowseUp),
+            'd' => Some(CallbackAction::BrowseAdd { slot: slot()? }),
+            'l' => Some(CallbackAction::LsOpen { slot: slot()? }),
+            'h' => Some(CallbackAction::LsPage { page: page()? }),
+            'U' => args.is_empty().then_some(CallbackAction::LsUp),
+            'D' => Some(CallbackAction::LsAdd { slot: slot()? }),
+            'c' => Some(CallbackAction::Player(PlayerControl::parse(args)?)),
+            _ => None,
+        }
+    }
+
+    /// A button for the action, `None` when its payload is too long for Telegram
+    pub fn try_button(&self, text: impl Into<String>) -> Option<InlineKeyboardButton> {
+        let data = self.encode();
+        (data.len() <= MAX_CALLBACK_DATA).then(|| InlineKeyboardButton::callback(text, data))
+    }
+
+    /// A button for an action whose payload always fits
+    pub fn button(&self, text: impl Into<String>) -> InlineKeyboardButton {
+        InlineKeyboardButton::callback(text, self.encode())
+    }
+}
+
+/// The ◀ n/N ▶ row of a paged list, going around at both ends. `None` when
+/// there's a single page.
+pub fn page_row(
+    page: u32,
+    pages: u32,
+    action: impl Fn(u32) -> CallbackAction,
+) -> Option<Vec<InlineKeyboardButton>> {
+    if pages <= 1 {
+        return None;
+    }
+    let prev = page.checked_sub(1).unwrap_or(pages - 1);
+    let next = (page + 1) % pages;
+    Some(vec![
+        action(prev).button("◀"),
+        action(page).button(format!("{}/{pages}", page + 1)),
+        action(next).button("▶"),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    use super::*;
+
+    fn every_action() -> Vec<CallbackAction> {
+        let slot = u16::MAX;
+        let page = u32::MAX;
+        let song = Id(u32::MAX);
+        vec![
+            CallbackAction::SearchResult { slot },
+            CallbackAction::PickPlaylist { slot },
+            CallbackAction::Song {
+                slot,
+                action: SongAction::PlayNext,
+            },
+            CallbackAction::Song {
+                slot,
+                action: SongAction::Append,
+            },
+            CallbackAction::Song {
+                slot,
+                action: SongAction::PlayNow,
+            },
+            CallbackAction::Song {
+                slot,
+                action: SongAction::AddToPlaylist("road: trip".into()),
+            },
+            CallbackAction::SearchPage { page },
+            CallbackAction::AddAllResults,
+            CallbackAction::VoteSkip { song },
+            CallbackAction::ForceSkip { song },
+            CallbackAction::Volume(VolumeChange::Set(100)),
+            CallbackAction::Volume(VolumeChange::By(-128)),
+            CallbackAction::Volume(VolumeChange::By(5)),
+            CallbackAction::ToggleMode(ModeToggle::Repeat),
+            CallbackAction::ToggleMode(ModeToggle::Random),
+            CallbackAction::ToggleMode(ModeToggle::Single),
+            CallbackAction::ToggleMode(ModeToggle::Consume),
+            CallbackAction::ToggleMode(ModeToggle::Crossfade),
+            CallbackAction::PlayId { song },
+            CallbackAction::QueuePage { page },
+            CallbackAction::QueuePlayNext { song, page },
+            CallbackAction::QueueRemove { song, page },
+            CallbackAction::LoadPlaylist { name: "a:b".into() },
+            CallbackAction::AppendPlaylist {
+                name: "chill".into(),
+            },
+            CallbackAction::BrowseOpen { slot },
+            CallbackAction::BrowsePage { page },
+            CallbackAction::BrowseUp,
+            CallbackAction::BrowseAdd { slot },
+            CallbackAction::LsOpen { slot },
+            CallbackAction::LsPage { page },
+            CallbackAction::LsUp,
+            CallbackAction::LsAdd { slot },
+            CallbackAction::Player(PlayerControl::Prev),
+            CallbackAction::Player(PlayerControl::Toggle),
+            CallbackAction::Player(PlayerControl::Next),
+        ]
+    }
+
+    #[test]
+    fn round_trips() {
+        for action in every_action() {
+            let data = action.encode();
+            let parsed =
+                CallbackAction::parse(&data).unwrap_or_else(|| panic!("{data} didn't parse back"));
+            assert_eq!(format!("{parsed:?}"), format!("{action:?}"));
+        }
+    }
+
+    #[test]
+    fn fixed_payloads_fit() {
+        for action in every_action() {
+            if matches!(
+                action,
+                CallbackAction::LoadPlaylist { .. }
+                    | CallbackAction::AppendPlaylist { .. }
+                    | CallbackAction::Song {
+                        action: SongAction::AddToPlaylist(_),
+                        ..
+                    }
+            ) {
+                continue;
+            }
+            let data = action.encode();
+            assert!(data.len() <= MAX_CALLBACK_DATA, "{data} is too long");
+        }
+    }
+
+    #[test]
+    fn long_playlist_names_have_no_button() {
+        let name = |len| "x".repeat(len);
+        let load = |len| CallbackAction::LoadPlaylist { name: name(len) };
+        assert!(load(MAX_CALLBACK_DATA - 2).try_button("").is_some());
+        assert!(load(MAX_CALLBACK_DATA - 1).try_button("").is_none());
+
+        let add = CallbackAction::Song {
+            slot: 1,
+            action: SongAction::AddToPlaylist(name(MAX_CALLBACK_DATA)),
+        };
+        assert!(add.try_button("").is_none());
+    }
+
+    #[test]
+    fn page_rows_go_around() {
+        let row = |page, pages| {
+            let row = page_row(page, pages, |page| CallbackAction::QueuePage { page })?;
+            let buttons = row.into_iter().map(|x| match x.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => {
+                    match CallbackAction::parse(&data) {
+                        Some(CallbackAction::QueuePage { page }) => (x.text, page),
+                        action => panic!("{action:?} isn't a queue page"),
+                    }
+                }
+                kind => panic!("{kind:?} isn't a callback button"),
+            });
+            Some(buttons.collect::<Vec<_>>())
+        };
+        assert_eq!(row(0, 0), None);
+        assert_eq!(row(0, 1), None);
+        assert_eq!(
+            row(0, 3).unwrap(),
+            [("◀".into(), 2), ("1/3".into(), 0), ("▶".into(), 1)]
+        );
+        assert_eq!(
+            row(2, 3).unwrap(),
+            [("◀".into(), 1), ("3/3".into(), 2), ("▶".into(), 0)]
+        );
+    }
+
+    #[test]
+    fn old_payloads_are_rejected() {
+        for data in [
+            // Search results and the no-op button before versioned payloads,
+            // simple v7 uuids followed by `i`. Far enough in the future they
+            // start with the version of today's payloads.
+            "0197f3a2b1c47e8d9a0b1c2d3e4f5a6bi",
+            "019a0c5e7d2f7b31a8c4e6f90d1b2c3ai",
+            "1a0b2c3d4e5f7a6b8c9d0e1f2a3b4c5di",
+            "1d3c2b0a10b1726f9247bb680e5fe0c8i",
+            "1b20446810b17000924701680e5f00c8i",
+            "1c9e0d4a2f3b7c1d8e2f3a4b5c6d7e8fi",
+            "1e55044b10b1726f9247bb680e5fe0c8i",
+            "1f00000000007000800000000000000fi",
+            "15e55044f0b1726f9247bb680e5fe0c8i",
+            "n",
+            // Another version
+            "2i1",
+            "0v3",
+            "",
+            "1",
+            "1z",
+            "1i",
+            "1i70000",
+            "1t5",
+            "1Y3:",
+            "1L",
+            "1a1",
+        ] {
+            assert!(
+                CallbackAction::parse(data).is_none(),
+                "{data} shouldn't parse"
+            );
+        }
+    }
+}
@@ -1,51 +1,639 @@
-use log::warn;
-use mpd::{Client, Query};
-use std::{error::Error, fs, os::unix::net::UnixStream, path::PathBuf};
-use teloxide::prelude::*;
+use log::{info, warn};
+use mpd::{Query, Song, error::Error as MpdError};
+use std::error::Error;
+use teloxide::{ApiError, RequestError, prelude::*, types::ParseMode};
 
-use crate::MPD_SOCKET_PATH;
+use super::{
+    access::Role,
+    browse_view::{add_level, browse_page, parent},
+    callback_data::CallbackAction,
+    callback_state::{CallbackState, CallbackStore, VIEW_SLOT},
+    ls_view::{self, add_directory, list_directory, ls_page},
+    modes::Modes,
+    now_playing::PlayerControl,
+    presenter::SongView,
+    queue_view::queue_page,
+    rate_limit::RateLimiter,
+    rich_text::Markup,
+    search_query::SearchQuery,
+    search_view::{load_search, search_page, search_result},
+    song_actions::{SongAction, actions_keyboard, playlist_picker},
+    volume::{volume_keyboard, volume_text},
+    vote_skip::{SkipVotes, skip, vote_keyboard},
+};
+use crate::connection::MpdPool;
 pub type CallbackReturn = Result<(), Box<dyn Error + Send + Sync>>;
 
-pub async fn callback_query_handler(bot: Bot, q: CallbackQuery) -> CallbackReturn {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let Some(mut data) = q.data else {
-        return Ok(());
+const EXPIRED_TEXT: &str = "⌛ Results expired, search again";
+const EXPIRED_TEXT_BROWSE: &str = "⌛ This list expired, run /browse again";
+const EXPIRED_TEXT_LS: &str = "⌛ This list expired, run /ls again";
+
+/// Answer with a denial unless `role` is at least `needed`, returns whether
+/// the action can go on
+async fn require(
+    bot: &Bot,
+    q: &CallbackQuery,
+    role: Role,
+    needed: Role,
+    what: &str,
+) -> Result<bool, RequestError> {
+    if role >= needed {
+        return Ok(true);
+    }
+    bot.answer_callback_query(q.id.clone())
+        .text(format!("🔒 Only {} can {what}", needed.holders()))
+        .await?;
+    Ok(false)
+}
+
+/// Charge `songs` to the user's rate limit, answering with how long to wait
+/// when there isn't enough left. Returns whether the action has to stop.
+async fn rate_limited(
+    bot: &Bot,
+    q: &CallbackQuery,
+    role: Role,
+    limiter: &RateLimiter,
+    songs: u32,
+) -> Result<bool, RequestError> {
+    if role >= Role::Admin {
+        return Ok(false);
+    }
+    let Err(retry) = limiter.check(q.from.id, songs) else {
+        return Ok(false);
     };
-    let cmd = data.pop().unwrap();
-    match cmd {
-        'i' => {
-            let mut path = PathBuf::from("uuid/");
-            path.push(&data);
+    bot.answer_callback_query(q.id.clone())
+        .text(retry.text())
+        .await?;
+    Ok(true)
+}
 
-            let text = String::from_utf8_lossy(&fs::read(&path)?).to_string();
-            let Some(song) = mpd
-                .find(Query::new().and(mpd::Term::File, text), None)?
-                .into_iter()
-                .next()
-            else {
+pub async fn callback_query_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    mpd: MpdPool,
+    role: Role,
+    limiter: RateLimiter,
+    votes: SkipVotes,
+    store: CallbackStore,
+) -> CallbackReturn {
+    let Some(data) = q.data.clone() else {
+        return Ok(());
+    };
+    let Some(action) = CallbackAction::parse(&data) else {
+        warn!("Unknown callback data: {data}");
+        bot.answer_callback_query(q.id)
+            .text("This button is outdated, run the command again")
+            .await?;
+        return Ok(());
+    };
+    match action {
+        CallbackAction::SearchResult { slot } | CallbackAction::PickPlaylist { slot } => {
+            if !require(&bot, &q, role, Role::Dj, "add songs").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            if matches!(action, CallbackAction::PickPlaylist { .. }) {
+                let mut playlists = mpd.run(|conn| conn.playlists()).await?;
+                if playlists.is_empty() {
+                    bot.answer_callback_query(q.id)
+                        .text("No saved playlists, save the queue with /save name first")
+                        .await?;
+                    return Ok(());
+                }
+                playlists.sort_by_key(|x| x.name.to_lowercase());
+                bot.answer_callback_query(q.id).await?;
+                bot.edit_message_reply_markup(msg.chat().id, msg.id())
+                    .reply_markup(playlist_picker(slot, &playlists))
+                    .await?;
+                return Ok(());
+            }
+            let Some(file) = search_result(&store, msg.chat().id, msg.id(), slot)? else {
+                bot.answer_callback_query(q.id).text(EXPIRED_TEXT).await?;
+                return Ok(());
+            };
+            let Some(song) = find_song(&mpd, file).await? else {
+                bot.answer_callback_query(q.id)
+                    .text("This song isn't in the library anymore")
+                    .await?;
+                return Ok(());
+            };
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(
+                msg.chat().id,
+                msg.id(),
+                format!(
+                    "{}\n\nWhat should I do with it?",
+                    SongView::new(&song)
+                        .markup(Markup::Html)
+                        .with_duration()
+                        .full()
+                ),
+            )
+            .parse_mode(ParseMode::Html)
+            .reply_markup(actions_keyboard(slot))
+            .await?;
+        }
+        CallbackAction::Song { slot, action } => {
+            if !require(&bot, &q, role, Role::Dj, "add songs").await? {
+                return Ok(());
+            }
+            let Some(msg) = &q.message else {
+                return Ok(());
+            };
+            let Some(file) = search_result(&store, msg.chat().id, msg.id(), slot)? else {
+                bot.answer_callback_query(q.id).text(EXPIRED_TEXT).await?;
+                return Ok(());
+            };
+            let Some(song) = find_song(&mpd, file).await? else {
+                bot.answer_callback_query(q.id)
+                    .text("This song isn't in the library anymore")
+                    .await?;
+                return Ok(());
+            };
+            // Saving to a playlist leaves the queue alone
+            if !matches!(action, SongAction::AddToPlaylist(_))
+                && rate_limited(&bot, &q, role, &limiter, 1).await?
+            {
+                return Ok(());
+            }
+            let text = action.done_text();
+            match mpd.run(move |conn| action.apply(conn, song.clone())).await {
+                Ok(_) => {}
+                Err(MpdError::Server(e)) => {
+                    bot.answer_callback_query(q.id)
+                        .text(format!("❌ {}", e.detail))
+                        .await?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(msg.chat().id, msg.id(), text).await?;
+            store.clear(msg.chat().id, msg.id())?;
+        }
+        CallbackAction::SearchPage { page } => {
+            let Some(msg) = q.message else {
                 return Ok(());
             };
-            let Some(current) = mpd.currentsong()? else {
+            let (chat_id, msg_id) = (msg.chat().id, msg.id());
+            let Some((total, query)) = load_search(&store, chat_id, msg_id)? else {
+                bot.answer_callback_query(q.id).text(EXPIRED_TEXT).await?;
                 return Ok(());
             };
-            let Some(current) = current.place else {
+            let (text, kbd) =
+                search_page(&mpd, &store, chat_id, msg_id, total, &query, page).await?;
+            bot.answer_callback_query(q.id).await?;
+            match bot
+                .edit_message_text(msg.chat().id, msg.id(), text)
+                .reply_markup(kbd)
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::AddAllResults => {
+            if !require(&bot, &q, role, Role::Dj, "add songs").await? {
+                return Ok(());
+            }
+            let Some(msg) = &q.message else {
                 return Ok(());
             };
-            mpd.insert(song, current.pos as usize + 1)?;
+            let Some((total, query)) = load_search(&store, msg.chat().id, msg.id())? else {
+                bot.answer_callback_query(q.id).text(EXPIRED_TEXT).await?;
+                return Ok(());
+            };
+            if rate_limited(&bot, &q, role, &limiter, total).await? {
+                return Ok(());
+            }
+            match SearchQuery::parse(&query).add_all(&mpd).await {
+                Ok(_) => {}
+                Err(MpdError::Server(e)) => {
+                    bot.answer_callback_query(q.id)
+                        .text(format!("❌ {}", e.detail))
+                        .await?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+            info!("Added {total} search results to queue");
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(
+                msg.chat().id,
+                msg.id(),
+                format!("✅ Added {total} songs to the queue!"),
+            )
+            .await?;
+            store.clear(msg.chat().id, msg.id())?;
+        }
+        CallbackAction::VoteSkip { song } | CallbackAction::ForceSkip { song } => {
+            let forced = matches!(action, CallbackAction::ForceSkip { .. });
+            if forced && !require(&bot, &q, role, Role::Admin, "force skip").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let chat_id = msg.chat().id;
+            let current = mpd
+                .run(|conn| conn.currentsong())
+                .await?
+                .and_then(|x| x.place)
+                .map(|x| x.id);
+            if current != Some(song) {
+                bot.answer_callback_query(q.id)
+                    .text("This song isn't playing anymore")
+                    .await?;
+                bot.edit_message_text(chat_id, msg.id(), "🗳 Vote closed, the song already changed")
+                    .await?;
+                return Ok(());
+            }
+            if !forced {
+                let tally = votes.vote(chat_id, q.from.id, song);
+                if !tally.passed() {
+                    bot.answer_callback_query(q.id)
+                        .text("🗳 Vote counted")
+                        .await?;
+                    bot.edit_message_reply_markup(chat_id, msg.id())
+                        .reply_markup(vote_keyboard(song, tally))
+                        .await?;
+                    return Ok(());
+                }
+            }
+            if !votes.close(chat_id, song) {
+                bot.answer_callback_query(q.id)
+                    .text("⏭ Already skipped")
+                    .await?;
+                return Ok(());
+            }
+            if !mpd.run(move |conn| skip(conn, song)).await? {
+                bot.answer_callback_query(q.id)
+                    .text("This song isn't playing anymore")
+                    .await?;
+                bot.edit_message_text(chat_id, msg.id(), "🗳 Vote closed, the song already changed")
+                    .await?;
+                return Ok(());
+            }
+            info!("Skipped song {song} from vote");
             bot.answer_callback_query(q.id).await?;
-            let msg = q.message.unwrap();
-            bot.edit_message_text(msg.chat().id, msg.id(), "✅ Song added!")
+            let text = if forced {
+                "⏭ Skipped by an admin"
+            } else {
+                "⏭ Skipped by vote"
+            };
+            bot.edit_message_text(chat_id, msg.id(), text).await?;
+        }
+        CallbackAction::Volume(change) => {
+            if !require(&bot, &q, role, Role::Dj, "change the volume").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let current = mpd.run(|conn| conn.status()).await?.volume;
+            let volume = change.apply(current);
+            if current < 0 || volume == current {
+                bot.answer_callback_query(q.id).await?;
+                return Ok(());
+            }
+            mpd.run(move |conn| conn.volume(volume)).await?;
+            info!("Set volume to {volume}");
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(msg.chat().id, msg.id(), volume_text(volume))
+                .reply_markup(volume_keyboard())
+                .await?;
+        }
+        CallbackAction::ToggleMode(toggle) => {
+            if !require(&bot, &q, role, Role::Dj, "change modes").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let modes = Modes::fetch(&mpd).await?.toggle(&mpd, toggle).await?;
+            info!("Changed playback modes: {:?}", modes);
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(msg.chat().id, msg.id(), modes.text())
+                .reply_markup(modes.keyboard())
                 .await?;
-            fs::read_dir(path.parent().unwrap()).unwrap().for_each(|x| {
-                if let Ok(entry) = x {
-                    let _ = fs::remove_file(entry.path());
+        }
+        CallbackAction::PlayId { song } => {
+            if !require(&bot, &q, role, Role::Dj, "switch songs").await? {
+                return Ok(());
+            }
+            match mpd.run(move |conn| conn.switch(song)).await {
+                Ok(_) => {
+                    info!("Switched to song {song}");
+                    bot.answer_callback_query(q.id).text("▶ Playing").await?;
+                }
+                Err(MpdError::Server(_)) => {
+                    bot.answer_callback_query(q.id)
+                        .text("This song isn't in the queue anymore")
+                        .await?;
                 }
-            });
+                Err(e) => return Err(e.into()),
+            }
         }
-        'n' => {}
-        a => {
-            warn!("Unhandled callback query command: {a}");
+        CallbackAction::QueuePage { page } => {
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let (text, kbd) = queue_page(&mpd, Some(page)).await?;
+            bot.answer_callback_query(q.id).await?;
+            match bot
+                .edit_message_text(msg.chat().id, msg.id(), text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(kbd)
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::QueuePlayNext { song, page }
+        | CallbackAction::QueueRemove { song, page } => {
+            let remove = matches!(action, CallbackAction::QueueRemove { .. });
+            if !require(&bot, &q, role, Role::Dj, "change the queue").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let res = mpd
+                .run(move |conn| {
+                    if remove {
+                        return conn.delete(song);
+                    }
+                    let status = conn.status()?;
+                    let Some(current) = status.song else {
+                        return conn.shift(song, 0);
+                    };
+                    // From a keyboard sent before the song started playing
+                    if song == current.id {
+                        return Ok(());
+                    }
+                    let Some(pos) = conn.playlistid(song)?.and_then(|x| x.place) else {
+                        return conn.shift(song, current.pos as usize + 1);
+                    };
+                    // Taking the song out from before the current one shifts it up by one
+                    let to = if pos.pos < current.pos {
+                        current.pos
+                    } else {
+                        current.pos + 1
+                    };
+                    conn.shift(song, to as usize)
+                })
+                .await;
+            match res {
+                Ok(_) => {
+                    info!("Changed queue entry {song}, removed: {remove}");
+                    let text = if remove {
+                        "❌ Removed"
+                    } else {
+                        "⬆ Playing next"
+                    };
+                    bot.answer_callback_query(q.id).text(text).await?;
+                }
+                Err(MpdError::Server(_)) => {
+                    bot.answer_callback_query(q.id)
+                        .text("This song isn't in the queue anymore")
+                        .await?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let (text, kbd) = queue_page(&mpd, Some(page)).await?;
+            match bot
+                .edit_message_text(msg.chat().id, msg.id(), text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(kbd)
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::LoadPlaylist { ref name } | CallbackAction::AppendPlaylist { ref name } => {
+            let replace = matches!(action, CallbackAction::LoadPlaylist { .. });
+            if !require(&bot, &q, role, Role::Dj, "load playlists").await? {
+                return Ok(());
+            }
+            let playlist = name.clone();
+            let res = mpd
+                .run(move |conn| {
+                    if replace {
+                        conn.clear()?;
+                        conn.load(playlist.as_str(), ..)?;
+                        conn.play()
+                    } else {
+                        conn.load(playlist.as_str(), ..)
+                    }
+                })
+                .await;
+            match res {
+                Ok(_) => {
+                    info!("Loaded playlist {name}");
+                    let text = if replace {
+                        format!("▶ Playing {name}")
+                    } else {
+                        format!("➕ Added {name} to the queue")
+                    };
+                    bot.answer_callback_query(q.id).text(text).await?;
+                }
+                Err(MpdError::Server(e)) => {
+                    bot.answer_callback_query(q.id)
+                        .text(format!("❌ {}", e.detail))
+                        .await?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::BrowseOpen { .. } | CallbackAction::BrowseUp => {
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let (chat_id, msg_id) = (msg.chat().id, msg.id());
+            let level = match action {
+                CallbackAction::BrowseOpen { slot } => store.get(chat_id, msg_id, slot)?,
+                _ => store
+                    .get(chat_id, msg_id, VIEW_SLOT)?
+                    .and_then(|x| parent(&x)),
+            };
+            let Some(level) = level else {
+                bot.answer_callback_query(q.id)
+                    .text(EXPIRED_TEXT_BROWSE)
+                    .await?;
+                return Ok(());
+            };
+            let (text, kbd) = browse_page(&mpd, &store, chat_id, msg_id, level, 0).await?;
+            bot.answer_callback_query(q.id).await?;
+            bot.edit_message_text(chat_id, msg_id, text)
+                .reply_markup(kbd)
+                .await?;
+        }
+        CallbackAction::BrowsePage { page } => {
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let (chat_id, msg_id) = (msg.chat().id, msg.id());
+            let Some(level) = store.get(chat_id, msg_id, VIEW_SLOT)? else {
+                bot.answer_callback_query(q.id)
+                    .text(EXPIRED_TEXT_BROWSE)
+                    .await?;
+                return Ok(());
+            };
+            let (text, kbd) = browse_page(&mpd, &store, chat_id, msg_id, level, page).await?;
+            bot.answer_callback_query(q.id).await?;
+            match bot
+                .edit_message_text(chat_id, msg_id, text)
+                .reply_markup(kbd)
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::BrowseAdd { slot } => {
+            if !require(&bot, &q, role, Role::Dj, "add songs").await? {
+                return Ok(());
+            }
+            let Some(msg) = &q.message else {
+                return Ok(());
+            };
+            let Some(level) = store.get(msg.chat().id, msg.id(), slot)? else {
+                bot.answer_callback_query(q.id)
+                    .text(EXPIRED_TEXT_BROWSE)
+                    .await?;
+                return Ok(());
+            };
+            if rate_limited(&bot, &q, role, &limiter, limiter.burst()).await? {
+                return Ok(());
+            }
+            match mpd.run(move |conn| add_level(conn, &level)).await? {
+                Some(name) => {
+                    info!("Added {name} to queue from browse");
+                    bot.answer_callback_query(q.id)
+                        .text(format!("➕ Added {name} to the queue"))
+                        .await?;
+                }
+                None => {
+                    bot.answer_callback_query(q.id).await?;
+                }
+            }
+        }
+        CallbackAction::LsOpen { .. } | CallbackAction::LsUp | CallbackAction::LsPage { .. } => {
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            let (chat_id, msg_id) = (msg.chat().id, msg.id());
+            let (slot, page) = match action {
+                CallbackAction::LsOpen { slot } => (slot, 0),
+                CallbackAction::LsPage { page } => (VIEW_SLOT, page),
+                _ => (VIEW_SLOT, 0),
+            };
+            let Some(CallbackState::Directory { mut path }) = store.get(chat_id, msg_id, slot)?
+            else {
+                bot.answer_callback_query(q.id)
+                    .text(EXPIRED_TEXT_LS)
+                    .await?;
+                return Ok(());
+            };
+            if matches!(action, CallbackAction::LsUp) {
+                path = ls_view::parent(&path);
+            }
+            let entries = match list_directory(&mpd, &path).await {
+                Ok(entries) => entries,
+                Err(MpdError::Server(e)) => {
+                    bot.answer_callback_query(q.id)
+                        .text(format!("❌ {}", e.detail))
+                        .await?;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let (text, kbd) = ls_page(&store, chat_id, msg_id, &path, &entries, page)?;
+            bot.answer_callback_query(q.id).await?;
+            match bot
+                .edit_message_text(chat_id, msg_id, text)
+                .reply_markup(kbd)
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::LsAdd { slot } => {
+            if !require(&bot, &q, role, Role::Dj, "add songs").await? {
+                return Ok(());
+            }
+            let Some(msg) = &q.message else {
+                return Ok(());
+            };
+            let Some(CallbackState::Directory { path }) =
+                store.get(msg.chat().id, msg.id(), slot)?
+            else {
+                bot.answer_callback_query(q.id)
+                    .text(EXPIRED_TEXT_LS)
+                    .await?;
+                return Ok(());
+            };
+            // The root is the whole library
+            if path.is_empty()
+                && !require(&bot, &q, role, Role::Admin, "add the whole library").await?
+            {
+                return Ok(());
+            }
+            if rate_limited(&bot, &q, role, &limiter, limiter.burst()).await? {
+                return Ok(());
+            }
+            match add_directory(&mpd, &path).await {
+                Ok(_) => {
+                    info!("Added directory /{path} to queue");
+                    bot.answer_callback_query(q.id)
+                        .text(format!("➕ Added /{path} to the queue"))
+                        .await?;
+                }
+                Err(MpdError::Server(e)) => {
+                    bot.answer_callback_query(q.id)
+                        .text(format!("❌ {}", e.detail))
+                        .await?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        CallbackAction::Player(control) => {
+            if !require(&bot, &q, role, Role::Dj, "control playback").await? {
+                return Ok(());
+            }
+            let Some(msg) = q.message else {
+                return Ok(());
+            };
+            if matches!(control, PlayerControl::Next) && votes.enabled() && !msg.chat().is_private()
+            {
+                bot.answer_callback_query(q.id)
+                    .text("🗳 Songs are skipped by vote here, use /next")
+                    .await?;
+                return Ok(());
+            }
+            // The live message picks up the change from MPD by itself
+            mpd.run(move |conn| match control {
+                PlayerControl::Prev => conn.prev(),
+                PlayerControl::Toggle => conn.toggle_pause(),
+                PlayerControl::Next => conn.next(),
+            })
+            .await?;
+            info!("Live message control: {}", control.as_str());
+            bot.answer_callback_query(q.id).await?;
         }
     }
     Ok(())
 }
+
+async fn find_song(mpd: &MpdPool, file: String) -> Result<Option<Song>, MpdError> {
+    mpd.run(move |conn| {
+        conn.find(Query::new().and(mpd::Term::File, file.as_str()), None)
+            .map(|x| x.into_iter().next())
+    })
+    .await
+}
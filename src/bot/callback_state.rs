@@ -0,0 +1,193 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use teloxide::types::{ChatId, MessageId};
+
+/// How long inline buttons keep working after they were sent
+pub const CALLBACK_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often expired entries are removed from the DB
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Slot of what the message itself shows, its buttons use the slots after it
+pub const VIEW_SLOT: u16 = 0;
+
+/// Data behind an inline button that doesn't fit in Telegram's 64 bytes of
+/// callback data
+#[derive(Clone, Debug)]
+pub enum CallbackState {
+    /// A song from search results or a browsed album or directory
+    Song {
+        file: String,
+    },
+    /// A search whose result pages can be fetched again
+    Search {
+        total: u32,
+        query: String,
+    },
+    /// Every artist of the library, the top level of `/browse`
+    Artists,
+    Artist {
+        name: String,
+    },
+    Album {
+        artist: String,
+        album: String,
+    },
+    /// A directory of the music folder, `""` being the root
+    Directory {
+        path: String,
+    },
+}
+
+impl CallbackState {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            CallbackState::Song { file } => [b"f".as_slice(), file.as_bytes()].concat(),
+            CallbackState::Search { total, query } => {
+                [b"s".as_slice(), &total.to_be_bytes(), query.as_bytes()].concat()
+            }
+            CallbackState::Artists => b"A".to_vec(),
+            CallbackState::Directory { path } => [b"d".as_slice(), path.as_bytes()].concat(),
+            CallbackState::Artist { name } => [b"a".as_slice(), name.as_bytes()].concat(),
+            // Tags can't hold a NUL, so it separates the two
+            CallbackState::Album { artist, album } => {
+                [b"l".as_slice(), artist.as_bytes(), b"\0", album.as_bytes()].concat()
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (tag, rest) = bytes.split_first()?;
+        match tag {
+            b'f' => Some(CallbackState::Song {
+                file: String::from_utf8(rest.to_vec()).ok()?,
+            }),
+            b's' => Some(CallbackState::Search {
+                total: u32::from_be_bytes(rest.get(..4)?.try_into().ok()?),
+                query: String::from_utf8(rest.get(4..)?.to_vec()).ok()?,
+            }),
+            b'A' => Some(CallbackState::Artists),
+            b'd' => Some(CallbackState::Directory {
+                path: String::from_utf8(rest.to_vec()).ok()?,
+            }),
+            b'a' => Some(CallbackState::Artist {
+                name: String::from_utf8(rest.to_vec()).ok()?,
+            }),
+            b'l' => {
+                let (artist, album) = std::str::from_utf8(rest).ok()?.split_once('\0')?;
+                Some(CallbackState::Album {
+                    artist: artist.to_string(),
+                    album: album.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Callback states kept in the DB, scoped to the message holding the buttons
+/// so that each button only needs a small slot number in its callback data.
+#[derive(Clone)]
+pub struct CallbackStore {
+    tree: sled::Tree,
+}
+
+impl CallbackStore {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("callback_state")?,
+        })
+    }
+
+    pub fn put(
+        &self,
+        chat: ChatId,
+        msg: MessageId,
+        slot: u16,
+        state: &CallbackState,
+    ) -> sled::Result<()> {
+        let expires = unix_secs(SystemTime::now() + CALLBACK_TTL);
+        let value = [expires.to_be_bytes().as_slice(), &state.encode()].concat();
+        self.tree.insert(key(chat, msg, slot), value)?;
+        Ok(())
+    }
+
+    /// State behind a button, `None` once it expired
+    pub fn get(
+        &self,
+        chat: ChatId,
+        msg: MessageId,
+        slot: u16,
+    ) -> sled::Result<Option<CallbackState>> {
+        let Some(value) = self.tree.get(key(chat, msg, slot))? else {
+            return Ok(None);
+        };
+        if is_expired(&value) {
+            return Ok(None);
+        }
+        Ok(CallbackState::decode(&value[8..]))
+    }
+
+    /// Forget every state of a message, once its buttons are gone
+    pub fn clear(&self, chat: ChatId, msg: MessageId) -> sled::Result<()> {
+        for entry in self.tree.scan_prefix(message_prefix(chat, msg)) {
+            self.tree.remove(entry?.0)?;
+        }
+        Ok(())
+    }
+
+    pub fn purge_expired(&self) -> sled::Result<usize> {
+        let mut purged = 0;
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            if is_expired(&value) {
+                self.tree.remove(key)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Purge expired states every [`PURGE_INTERVAL`], meant to be spawned once
+    pub async fn purge_loop(self) {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let store = self.clone();
+            match tokio::task::spawn_blocking(move || store.purge_expired()).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(purged)) => info!("Purged {purged} expired callback states"),
+                Ok(Err(e)) => error!("{}", e),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+}
+
+fn message_prefix(chat: ChatId, msg: MessageId) -> [u8; 12] {
+    let mut prefix = [0; 12];
+    prefix[..8].copy_from_slice(&chat.0.to_be_bytes());
+    prefix[8..].copy_from_slice(&msg.0.to_be_bytes());
+    prefix
+}
+
+fn key(chat: ChatId, msg: MessageId, slot: u16) -> [u8; 14] {
+    let mut key = [0; 14];
+    key[..12].copy_from_slice(&message_prefix(chat, msg));
+    key[12..].copy_from_slice(&slot.to_be_bytes());
+    key
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn is_expired(value: &[u8]) -> bool {
+    let expires = value
+        .get(..8)
+        .and_then(|x| x.try_into().ok())
+        .map(u64::from_be_bytes)
+        .unwrap_or_default();
+    expires <= unix_secs(SystemTime::now())
+}
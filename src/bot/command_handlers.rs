@@ -1,28 +1,42 @@
 use log::{error, info};
-use mpd::{Client, Query, Song, search::Window};
+use mpd::{Id, Song, error::Error as MpdError};
 use rand::prelude::IndexedRandom;
-use std::{
-    env::temp_dir,
-    error::Error,
-    fs::{self, DirBuilder},
-    os::unix::net::UnixStream,
-    path::PathBuf,
-    process::Command,
-    time::Duration,
-};
+#[cfg(feature = "local")]
+use std::path::PathBuf;
+use std::{env::temp_dir, error::Error, fs::DirBuilder, time::Duration};
 #[cfg(feature = "local")]
 use teloxide::types::{FileId, InputFile};
 use teloxide::{
     net::Download,
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, ReactionType, ReplyParameters},
+    types::{ParseMode, ReactionType, Recipient, ReplyParameters},
     utils::command::BotCommands,
 };
-use tokio::fs::File as AsyncFile;
+use tokio::{fs::File as AsyncFile, process::Command};
 
-use crate::{MPD_SOCKET_PATH, REACTION_EMOJI};
+use crate::{REACTION_EMOJI, connection::MpdPool};
 
-use super::Commands;
+use super::{
+    Commands,
+    access::Role,
+    album_art::AlbumArt,
+    browse_view::browse_page,
+    callback_state::{CallbackState, CallbackStore},
+    ls_view::{add_directory, list_directory, ls_page, normalize},
+    modes::Modes,
+    now_playing::{LiveMessages, controls_keyboard, live_text},
+    playlists::{parse_rename, playlists_keyboard},
+    presenter::{self, SongView, Tag},
+    queue_view::queue_page,
+    rate_limit::RateLimiter,
+    rich_text::{MAX_MESSAGE_LEN, Markup},
+    search_query::SearchQuery,
+    search_view::{save_search, search_page},
+    seek::{SeekTarget, format_time},
+    subscriptions::Subscriptions,
+    volume::{VolumeChange, volume_keyboard, volume_text},
+    vote_skip::{SkipVotes, skip, vote_keyboard},
+};
 type HandlerResultErr = Box<dyn Error + Send + Sync>;
 type HandlerResult = Result<(), HandlerResultErr>;
 
@@ -38,9 +52,8 @@ pub async fn help(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn clear(bot: Bot, msg: Message) -> HandlerResult {
-    let mut conn = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    match conn.clear() {
+pub async fn clear(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    match mpd.run(|conn| conn.clear()).await {
         Ok(_) => {
             info!("Cleared queue");
             bot.set_message_reaction(msg.chat.id, msg.id)
@@ -57,9 +70,8 @@ pub async fn clear(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn play(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    match mpd.toggle_pause() {
+pub async fn play(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    match mpd.run(|conn| conn.toggle_pause()).await {
         Ok(_) => {
             info!("Toggled playback");
             bot.set_message_reaction(msg.chat.id, msg.id)
@@ -76,10 +88,52 @@ pub async fn play(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn next(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    match mpd.next() {
-        Ok(_) => {
+pub async fn next(
+    bot: Bot,
+    msg: Message,
+    mpd: MpdPool,
+    votes: SkipVotes,
+    art: AlbumArt,
+) -> HandlerResult {
+    let mut voted = None;
+    if votes.enabled()
+        && !msg.chat.is_private()
+        && let Some(user) = &msg.from
+    {
+        let Some(song) = mpd.run(|conn| conn.currentsong()).await? else {
+            bot.send_message(msg.chat.id, "No song playing right now")
+                .await?;
+            return Ok(());
+        };
+        let Some(place) = song.place else {
+            return Ok(());
+        };
+        let tally = votes.vote(msg.chat.id, user.id, place.id);
+        if !tally.passed() {
+            let title = Markup::Html.bold(presenter::title(&song));
+            bot.send_message(msg.chat.id, format!("🗳 Vote to skip 🎵 {title}"))
+                .parse_mode(ParseMode::Html)
+                .reply_markup(vote_keyboard(place.id, tally))
+                .await?;
+            return Ok(());
+        }
+        if !votes.close(msg.chat.id, place.id) {
+            // Another vote skipped it at the same time
+            bot.send_message(msg.chat.id, "⏭ Already skipped").await?;
+            return Ok(());
+        }
+        voted = Some(place.id);
+    }
+
+    let skipped = mpd
+        .run(move |conn| match voted {
+            Some(song) => skip(conn, song),
+            None => conn.next().map(|_| true),
+        })
+        .await;
+    match skipped {
+        Ok(false) => return Ok(()),
+        Ok(true) => {
             info!("Next song");
             bot.set_message_reaction(msg.chat.id, msg.id)
                 .reaction(vec![ReactionType::Emoji {
@@ -91,37 +145,18 @@ pub async fn next(bot: Bot, msg: Message) -> HandlerResult {
             error!("{}", t);
         }
     };
-    let Some(song) = mpd.currentsong()? else {
+    let Some(song) = mpd.run(|conn| conn.currentsong()).await? else {
         return Ok(());
     };
-    let title = song.title.unwrap_or("Unknown".into());
-    let artist = song.artist.unwrap_or("Unknown".into());
-    let album = song
-        .tags
-        .into_iter()
-        .filter_map(|(name, val)| {
-            if name.to_lowercase().as_str() == "album" {
-                Some(val)
-            } else {
-                None
-            }
-        })
-        .collect::<String>();
-
-    let text = format!("🎵 {title}\n👤 {artist}\n💿 {album}");
-    bot.send_message(msg.chat.id, text)
-        .reply_parameters(ReplyParameters {
-            message_id: msg.id,
-            ..Default::default()
-        })
+    let text = SongView::new(&song).markup(Markup::Html).full();
+    art.send(&bot, &mpd, msg.chat.id, Some(msg.id), &song.file, text)
         .await?;
 
     Ok(())
 }
 
-pub async fn prev(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    match mpd.prev() {
+pub async fn prev(bot: Bot, msg: Message, mpd: MpdPool, art: AlbumArt) -> HandlerResult {
+    match mpd.run(|conn| conn.prev()).await {
         Ok(_) => {
             info!("Prev song");
             bot.set_message_reaction(msg.chat.id, msg.id)
@@ -135,37 +170,18 @@ pub async fn prev(bot: Bot, msg: Message) -> HandlerResult {
         }
     };
 
-    let Some(song) = mpd.currentsong()? else {
+    let Some(song) = mpd.run(|conn| conn.currentsong()).await? else {
         return Ok(());
     };
-    let title = song.title.unwrap_or("Unknown".into());
-    let artist = song.artist.unwrap_or("Unknown".into());
-    let album = song
-        .tags
-        .into_iter()
-        .filter_map(|(name, val)| {
-            if name.to_lowercase().as_str() == "album" {
-                Some(val)
-            } else {
-                None
-            }
-        })
-        .collect::<String>();
-
-    let text = format!("🎵 {title}\n👤 {artist}\n💿 {album}");
-    bot.send_message(msg.chat.id, text)
-        .reply_parameters(ReplyParameters {
-            message_id: msg.id,
-            ..Default::default()
-        })
+    let text = SongView::new(&song).markup(Markup::Html).full();
+    art.send(&bot, &mpd, msg.chat.id, Some(msg.id), &song.file, text)
         .await?;
 
     Ok(())
 }
 
-pub async fn shuffle(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    match mpd.shuffle(..) {
+pub async fn shuffle(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    match mpd.run(|conn| conn.shuffle(..)).await {
         Ok(_) => {
             info!("Prev song");
             bot.set_message_reaction(msg.chat.id, msg.id)
@@ -182,10 +198,117 @@ pub async fn shuffle(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn curr(bot: Bot, msg: Message) -> HandlerResult {
+pub async fn volume(bot: Bot, msg: Message, mpd: MpdPool, change: String) -> HandlerResult {
+    let current = mpd.run(|conn| conn.status()).await?.volume;
+    if current < 0 {
+        bot.send_message(msg.chat.id, "❌ Volume can't be changed, mpd has no mixer")
+            .await?;
+        return Ok(());
+    }
+    if change.trim().is_empty() {
+        bot.send_message(msg.chat.id, volume_text(current))
+            .reply_markup(volume_keyboard())
+            .await?;
+        return Ok(());
+    }
+    let Some(change) = VolumeChange::parse(&change) else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid volume\nUsage: /volume 50, /volume +10 or /volume -5",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let volume = change.apply(current);
+    mpd.run(move |conn| conn.volume(volume)).await?;
+    info!("Set volume to {volume}");
+    bot.send_message(msg.chat.id, volume_text(volume))
+        .reply_parameters(ReplyParameters {
+            message_id: msg.id,
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn seek(bot: Bot, msg: Message, mpd: MpdPool, target: String) -> HandlerResult {
+    let Some(target) = SeekTarget::parse(&target) else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid position\nUsage: /seek 1:30, /seek +30, /seek -15 or /seek 50%",
+        )
+        .await?;
+        return Ok(());
+    };
+    let seeked = mpd
+        .run(move |conn| {
+            let status = conn.status()?;
+            let (Some(elapsed), Some(duration)) = (status.elapsed, status.duration) else {
+                return Ok(None);
+            };
+            let pos = target.resolve(elapsed, duration);
+            conn.rewind(pos)?;
+            Ok(conn.currentsong()?.map(|song| (song, pos, duration)))
+        })
+        .await?;
+    let Some((song, pos, duration)) = seeked else {
+        bot.send_message(msg.chat.id, "No song playing right now")
+            .await?;
+        return Ok(());
+    };
+    info!("Seeked to {}", format_time(pos));
+
+    let text = SongView::new(&song)
+        .markup(Markup::Html)
+        .with_position(pos, duration)
+        .full();
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_parameters(ReplyParameters {
+            message_id: msg.id,
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn modes(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    let modes = Modes::fetch(&mpd).await?;
+    bot.send_message(msg.chat.id, modes.text())
+        .reply_markup(modes.keyboard())
+        .await?;
+    Ok(())
+}
+
+pub async fn curr(
+    bot: Bot,
+    msg: Message,
+    mode: String,
+    mpd: MpdPool,
+    live: LiveMessages,
+    art: AlbumArt,
+    #[cfg(feature = "local")] db: sled::Db,
+) -> HandlerResult {
+    match mode.trim() {
+        "" => {}
+        "live" => {
+            let sent = bot
+                .send_message(msg.chat.id, live_text(&mpd).await?)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(controls_keyboard())
+                .await?;
+            live.start(bot, mpd, msg.chat.id, sent.id);
+            return Ok(());
+        }
+        _ => {
+            bot.send_message(msg.chat.id, "Usage: /np or /np live")
+                .await?;
+            return Ok(());
+        }
+    }
     info!("Current song info sent");
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let song = match mpd.currentsong()? {
+    let song = match mpd.run(|conn| conn.currentsong()).await? {
         Some(t) => t,
         None => {
             bot.send_message(msg.chat.id, "No song playing right now")
@@ -193,29 +316,23 @@ pub async fn curr(bot: Bot, msg: Message) -> HandlerResult {
             return Ok(());
         }
     };
-    let title = song.title.unwrap_or("Unknown".into());
-    let artist = song.artist.unwrap_or("Unknown".into());
-    let album = song
-        .tags
-        .into_iter()
-        .filter_map(|(name, val)| {
-            if name.to_lowercase().as_str() == "album" {
-                Some(val)
-            } else {
-                None
-            }
-        })
-        .collect::<String>();
-
-    let text = format!("🎵 {title}\n👤 {artist}\n💿 {album}");
-    bot.send_message(msg.chat.id, text).await?;
+    let text = SongView::new(&song)
+        .markup(Markup::Html)
+        .with_tags(&[Tag::Year, Tag::Genre, Tag::Track])
+        .with_duration()
+        .full();
+    art.send(&bot, &mpd, msg.chat.id, None, &song.file, text)
+        .await?;
 
     #[cfg(feature = "local")]
     {
+        let title = presenter::title(&song).to_string();
+        let artist = presenter::artist(&song).to_string();
         tokio::spawn(async move {
             let file_name = song.file;
             let Some(file_path) = mpd
-                .mounts()?
+                .run(|conn| conn.mounts())
+                .await?
                 .into_iter()
                 .filter_map(|x| {
                     let path = format!("{}/{}", x.storage, file_name);
@@ -236,7 +353,6 @@ pub async fn curr(bot: Bot, msg: Message) -> HandlerResult {
             };
             info!("Got file path");
 
-            let db = sled::open("DB")?;
             if let Some(sticker) = db.get(&file_name)? {
                 let sticker = String::from_utf8_lossy(&sticker).to_string();
                 info!("Sending via file id");
@@ -270,48 +386,295 @@ pub async fn curr(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn queue(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let Some(current) = mpd.currentsong()? else {
+pub async fn queue(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    let (text, kbd) = match queue_page(&mpd, None).await {
+        Ok(t) => t,
+        Err(t) => {
+            error!("{}", t);
+            return Ok(());
+        }
+    };
+    info!("Queue info sent");
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kbd)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn remove(bot: Bot, msg: Message, mpd: MpdPool, range: String) -> HandlerResult {
+    // Positions start at 1 like in /queue, ranges include both ends
+    let range = match range.trim().split_once('-') {
+        Some((from, to)) => from
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .zip(to.trim().parse::<u32>().ok()),
+        None => range.trim().parse::<u32>().ok().map(|x| (x, x)),
+    }
+    .filter(|(from, to)| *from > 0 && from <= to);
+    let Some((from, to)) = range else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid position\nUsage: /remove 3 or /remove 3-5 (positions like in /queue)",
+        )
+        .await?;
         return Ok(());
     };
-    let Some(current) = current.place else {
+    match mpd.run(move |conn| conn.delete(from - 1..to)).await {
+        Ok(_) => {
+            info!("Removed songs {from}-{to} from queue");
+            bot.set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji {
+                    emoji: REACTION_EMOJI.into(),
+                }])
+                .await?;
+        }
+        Err(MpdError::Server(e)) => {
+            bot.send_message(msg.chat.id, format!("❌ Can't remove that: {}", e.detail))
+                .await?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+pub async fn move_song(bot: Bot, msg: Message, mpd: MpdPool, args: String) -> HandlerResult {
+    let positions = args
+        .split_whitespace()
+        .map(|x| x.parse::<u32>().ok().filter(|x| *x > 0))
+        .collect::<Option<Vec<_>>>();
+    let Some(&[from, to]) = positions.as_deref() else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid positions\nUsage: /move 7 2 (moves song 7 to position 2)",
+        )
+        .await?;
         return Ok(());
     };
-    let current = current.pos as usize;
-    let queue = match mpd.queue() {
+    match mpd
+        .run(move |conn| conn.shift(from - 1, to as usize - 1))
+        .await
+    {
+        Ok(_) => {
+            info!("Moved song {from} to {to}");
+            bot.set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji {
+                    emoji: REACTION_EMOJI.into(),
+                }])
+                .await?;
+        }
+        Err(MpdError::Server(e)) => {
+            bot.send_message(msg.chat.id, format!("❌ Can't move that: {}", e.detail))
+                .await?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+pub async fn playlists(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    let mut playlists = mpd.run(|conn| conn.playlists()).await?;
+    if playlists.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No saved playlists\nSave the queue with /save name",
+        )
+        .await?;
+        return Ok(());
+    }
+    playlists.sort_by_key(|x| x.name.to_lowercase());
+    let names = playlists
+        .iter()
+        .map(|x| format!("📃 {}", x.name))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = format!(
+        "{} playlists saved:\n\n{names}\n\nTap ▶ to replace the queue or ➕ to append",
+        playlists.len()
+    );
+    info!("Playlists sent");
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(playlists_keyboard(&playlists))
+        .await?;
+    Ok(())
+}
+
+pub async fn playlist_show(bot: Bot, msg: Message, mpd: MpdPool, name: String) -> HandlerResult {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No playlist name\nUsage: /playlistshow road trip",
+        )
+        .await?;
+        return Ok(());
+    }
+    let songs = match mpd.run(move |conn| conn.playlist(name.as_str())).await {
         Ok(t) => t,
-        Err(t) => {
-            error!("{}", t);
+        Err(MpdError::Server(e)) => {
+            bot.send_message(msg.chat.id, format!("❌ {}", e.detail))
+                .await?;
             return Ok(());
         }
+        Err(e) => return Err(e.into()),
     };
-    let queue = Vec::from(&queue[current..]);
-    let queue_len = queue.len();
-    let mut queue_formatted = queue
-        .into_iter()
-        .map(|f| {
-            [
-                "🎵".into(),
-                f.title.unwrap_or("Unknown".into()),
-                "-".into(),
-                f.artist.unwrap_or("Unknown".into()),
-            ]
-            .join(" ")
-        })
-        .take(20)
-        .collect::<Vec<String>>()
-        .join("\n\n");
-    if queue_formatted.is_empty() {
-        queue_formatted = "No song in queue".into();
+    let songs_len = songs.len();
+    let mut text = format!("📃 Playlist length: {songs_len}\n");
+    let mut shown = 0;
+    for (i, f) in songs.iter().enumerate().take(50) {
+        let song = SongView::new(f).markup(Markup::Html).compact();
+        let line = format!("\n{}. 🎵 {song}", i + 1);
+        // Markup counts too, and room is left for the line about the rest
+        if text.encode_utf16().count() + line.encode_utf16().count() + 32 > MAX_MESSAGE_LEN {
+            break;
+        }
+        text += &line;
+        shown += 1;
     }
-    let text = format!("🎛Queue length: {}\n\n{queue_formatted}", queue_len);
-    info!("Queue info sent");
-    bot.send_message(msg.chat.id, text).await?;
+    if songs.is_empty() {
+        text += "\nNo song in playlist";
+    } else if shown < songs_len {
+        text += &format!("\n\n… and {} more", songs_len - shown);
+    }
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+pub async fn save_playlist(bot: Bot, msg: Message, mpd: MpdPool, name: String) -> HandlerResult {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        bot.send_message(msg.chat.id, "No playlist name\nUsage: /save road trip")
+            .await?;
+        return Ok(());
+    }
+    let res = mpd.run(move |conn| conn.save(name.as_str())).await;
+    playlist_done(bot, msg, res).await
+}
+
+pub async fn load_playlist(bot: Bot, msg: Message, mpd: MpdPool, name: String) -> HandlerResult {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        bot.send_message(msg.chat.id, "No playlist name\nUsage: /load road trip")
+            .await?;
+        return Ok(());
+    }
+    let res = mpd.run(move |conn| conn.load(name.as_str(), ..)).await;
+    playlist_done(bot, msg, res).await
+}
+
+pub async fn remove_playlist(bot: Bot, msg: Message, mpd: MpdPool, name: String) -> HandlerResult {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "No playlist name\nUsage: /rmplaylist road trip",
+        )
+        .await?;
+        return Ok(());
+    }
+    let res = mpd.run(move |conn| conn.pl_remove(name.as_str())).await;
+    playlist_done(bot, msg, res).await
+}
+
+pub async fn rename_playlist(bot: Bot, msg: Message, mpd: MpdPool, args: String) -> HandlerResult {
+    let Some((from, to)) = parse_rename(&args) else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid names\nUsage: /renameplaylist old new, or /renameplaylist old name | new name",
+        )
+        .await?;
+        return Ok(());
+    };
+    let res = mpd
+        .run(move |conn| conn.pl_rename(from.as_str(), &to))
+        .await;
+    playlist_done(bot, msg, res).await
+}
+
+async fn playlist_done(bot: Bot, msg: Message, res: Result<(), MpdError>) -> HandlerResult {
+    match res {
+        Ok(_) => {
+            info!("Changed stored playlists");
+            bot.set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji {
+                    emoji: REACTION_EMOJI.into(),
+                }])
+                .await?;
+        }
+        Err(MpdError::Server(e)) => {
+            bot.send_message(msg.chat.id, format!("❌ {}", e.detail))
+                .await?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+pub async fn stop(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    match mpd.run(|conn| conn.stop()).await {
+        Ok(_) => {
+            info!("Stopped playback");
+            bot.set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji {
+                    emoji: REACTION_EMOJI.into(),
+                }])
+                .await?;
+        }
+        Err(t) => {
+            error!("{}", t);
+        }
+    };
 
     Ok(())
 }
 
+pub async fn play_pos(bot: Bot, msg: Message, mpd: MpdPool, pos: String) -> HandlerResult {
+    let Some(pos) = pos.trim().parse::<u32>().ok().filter(|x| *x > 0) else {
+        bot.send_message(
+            msg.chat.id,
+            "Invalid position\nUsage: /playpos 3 (positions start at 1, like in /queue)",
+        )
+        .await?;
+        return Ok(());
+    };
+    play_at(bot, msg, mpd.run(move |conn| conn.switch(pos - 1)).await).await
+}
+
+pub async fn play_id(bot: Bot, msg: Message, mpd: MpdPool, id: String) -> HandlerResult {
+    let Ok(id) = id.trim().parse::<u32>() else {
+        bot.send_message(msg.chat.id, "Invalid song id\nUsage: /playid 42")
+            .await?;
+        return Ok(());
+    };
+    play_at(bot, msg, mpd.run(move |conn| conn.switch(Id(id))).await).await
+}
+
+async fn play_at(bot: Bot, msg: Message, res: Result<(), MpdError>) -> HandlerResult {
+    match res {
+        Ok(_) => {
+            info!("Switched song");
+            bot.set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji {
+                    emoji: REACTION_EMOJI.into(),
+                }])
+                .await?;
+        }
+        Err(MpdError::Server(e)) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("❌ Can't play that song: {}", e.detail),
+            )
+            .await?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
 pub async fn add_yt(bot: Bot, msg: Message) -> HandlerResult {
     let Some(Some(url)) = msg
         .reply_to_message()
@@ -351,25 +714,29 @@ pub async fn add_yt(bot: Bot, msg: Message) -> HandlerResult {
         .arg("addyt")
         .arg("-p")
         .arg("+0")
-        .arg(url)
+        .arg(&url)
         .spawn()
     {
         Ok(mut f) => {
-            if f.wait()?.success() {
-                bot.send_message(msg.chat.id, "✅ Added youtube song to queue!")
+            if f.wait().await?.success() {
+                let song = Markup::Html.link(&url, "youtube song");
+                bot.send_message(msg.chat.id, format!("✅ Added {song} to queue!"))
+                    .parse_mode(ParseMode::Html)
                     .await?;
             } else {
-                bot.send_message(msg.chat.id, "❌ Failed to add song".to_string())
-                    .parse_mode(teloxide::types::ParseMode::MarkdownV2)
+                bot.send_message(msg.chat.id, "❌ Failed to add song")
                     .await?;
             }
         }
         Err(e) => {
             bot.send_message(
                 msg.chat.id,
-                format!("❌ Failed to add song:\n```\n{e}\n```"),
+                format!(
+                    "❌ Failed to add song:\n{}",
+                    Markup::Html.pre(&e.to_string())
+                ),
             )
-            .parse_mode(teloxide::types::ParseMode::MarkdownV2)
+            .parse_mode(ParseMode::Html)
             .await?;
         }
     };
@@ -382,9 +749,8 @@ fn humanize_duration(dur: Duration) -> String {
     dur.human(Truncate::Second).to_string()
 }
 
-pub async fn stats(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let stats = mpd.stats()?;
+pub async fn stats(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    let stats = mpd.run(|conn| conn.stats()).await?;
     let artists = stats.artists;
     let albums = stats.albums;
     let songs = stats.songs;
@@ -402,75 +768,235 @@ total duration: {db_playtime}"#
     Ok(())
 }
 
-pub async fn search(bot: Bot, msg: Message, query: String) -> HandlerResult {
+pub async fn search(
+    bot: Bot,
+    msg: Message,
+    query: String,
+    mpd: MpdPool,
+    store: CallbackStore,
+) -> HandlerResult {
+    let text = query;
+    let query = SearchQuery::parse(&text);
     if query.is_empty() {
+        let m = Markup::Html;
+        let examples = [
+            "/search enter sandman",
+            "/search artist:radiohead album:\"ok computer\" creep",
+            "/search --exact genre:Rock",
+        ]
+        .map(|x| format!("    {}", m.code(x)))
+        .join("\n");
+        let fields = "artist, albumartist, album, title, year, genre, composer, track, file";
         bot.send_message(
             msg.chat.id,
-            "No search query\nUsage:\n    `/search enter sandman`",
+            format!(
+                "No search query\nUsage:\n{examples}\n\nFields: {}",
+                m.escape(fields)
+            ),
         )
-        .parse_mode(teloxide::types::ParseMode::MarkdownV2)
+        .parse_mode(ParseMode::Html)
         .await?;
         return Ok(());
     }
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let mut q = Query::new();
-    let query_mpd = q.and(mpd::Term::Tag("Title".into()), &query);
-    let buttons = mpd
-        .search(query_mpd, Window::from((0, 95)))?
-        .into_iter()
-        .map(|f| {
-            let title = f.title.unwrap_or("Unknown".into());
-            let artist = f.artist.unwrap_or("Unknown".into());
-            let text = [artist, "-".into(), title].join(" ");
-            let id = uuid::Uuid::now_v7().as_simple().to_string();
-            let mut path = PathBuf::from("uuid/");
-            path.push(&id);
-            let _ = fs::write(path, f.file);
-            (text, id)
-        })
-        .map(|(text, id)| vec![InlineKeyboardButton::callback(text, format!("{}i", id))])
-        .collect::<Vec<_>>();
+    let total = query.count(&mpd).await?;
+    if total == 0 {
+        bot.send_message(msg.chat.id, "No results found!").await?;
+        return Ok(());
+    }
 
-    if !buttons.is_empty() {
-        let btns = buttons.len();
-        let kbd = InlineKeyboardMarkup::new(buttons);
-        bot.send_message(
-            msg.chat.id,
-            format!("{} resluts found. Tap on a button to add to queue:", btns),
-        )
+    // Results are stored per message, so the message has to exist first
+    let sent = bot
+        .send_message(msg.chat.id, format!("🔎 {total} results found"))
+        .await?;
+    save_search(&store, msg.chat.id, sent.id, total, text.clone())?;
+    let (reply, kbd) = search_page(&mpd, &store, msg.chat.id, sent.id, total, &text, 0).await?;
+    bot.edit_message_text(msg.chat.id, sent.id, reply)
         .reply_markup(kbd)
         .await?;
-    } else {
-        bot.send_message(msg.chat.id, "No results found!").await?;
-    }
 
     Ok(())
 }
 
-pub async fn add_rand(bot: Bot, msg: Message, amount: String) -> HandlerResult {
-    let amount = if amount.is_empty() {
-        "1".into()
+pub async fn browse(bot: Bot, msg: Message, mpd: MpdPool, store: CallbackStore) -> HandlerResult {
+    // Entries are stored per message, so the message has to exist first
+    let sent = bot.send_message(msg.chat.id, "🎤 Loading artists…").await?;
+    let (text, kbd) = browse_page(
+        &mpd,
+        &store,
+        msg.chat.id,
+        sent.id,
+        CallbackState::Artists,
+        0,
+    )
+    .await?;
+    bot.edit_message_text(msg.chat.id, sent.id, text)
+        .reply_markup(kbd)
+        .await?;
+    Ok(())
+}
+
+pub async fn ls(
+    bot: Bot,
+    msg: Message,
+    path: String,
+    mpd: MpdPool,
+    store: CallbackStore,
+) -> HandlerResult {
+    let path = normalize(&path);
+    let entries = match list_directory(&mpd, &path).await {
+        Ok(entries) => entries,
+        Err(MpdError::Server(e)) => {
+            bot.send_message(msg.chat.id, format!("❌ {}", e.detail))
+                .await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Entries are stored per message, so the message has to exist first
+    let sent = bot.send_message(msg.chat.id, format!("📂 /{path}")).await?;
+    let (text, kbd) = ls_page(&store, msg.chat.id, sent.id, &path, &entries, 0)?;
+    bot.edit_message_text(msg.chat.id, sent.id, text)
+        .reply_markup(kbd)
+        .await?;
+    Ok(())
+}
+
+pub async fn subscribe(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    role: Role,
+    subs: Subscriptions,
+) -> HandlerResult {
+    let Some((chat, silent)) = subscription_target(&bot, &msg, &args, role).await? else {
+        return Ok(());
+    };
+    let existed = subs.subscribe(chat, silent)?;
+    info!("Subscribed {chat}, silent: {silent}");
+    let text = match (existed, silent) {
+        (true, _) => "🔔 Subscription updated",
+        (false, true) => "🔔 Subscribed, song changes will be posted silently",
+        (false, false) => "🔔 Subscribed, song changes will be posted",
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+pub async fn unsubscribe(
+    bot: Bot,
+    msg: Message,
+    args: String,
+    role: Role,
+    subs: Subscriptions,
+) -> HandlerResult {
+    let Some((chat, _)) = subscription_target(&bot, &msg, &args, role).await? else {
+        return Ok(());
+    };
+    let text = if subs.unsubscribe(chat)? {
+        info!("Unsubscribed {chat}");
+        "🔕 Unsubscribed"
     } else {
-        amount
+        "This chat isn't subscribed"
     };
-    let Ok(amount) = amount.parse::<usize>() else {
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+/// Chat and `silent` flag of `/subscribe` arguments. Admins can name another
+/// chat or channel by id or @username, everyone else only (un)subscribes the
+/// chat they write in.
+async fn subscription_target(
+    bot: &Bot,
+    msg: &Message,
+    args: &str,
+    role: Role,
+) -> Result<Option<(ChatId, bool)>, HandlerResultErr> {
+    let mut silent = false;
+    let mut target = None;
+    for arg in args.split_whitespace() {
+        if arg.eq_ignore_ascii_case("silent") {
+            silent = true;
+        } else {
+            target = Some(arg);
+        }
+    }
+    let Some(target) = target else {
+        return Ok(Some((msg.chat.id, silent)));
+    };
+    if role < Role::Admin {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "🔒 Only {} can subscribe other chats",
+                Role::Admin.holders()
+            ),
+        )
+        .await?;
+        return Ok(None);
+    }
+    let chat = match target.parse::<i64>() {
+        Ok(id) => Ok(ChatId(id)),
+        Err(_) => bot
+            .get_chat(Recipient::ChannelUsername(target.into()))
+            .await
+            .map(|x| x.id),
+    };
+    match chat {
+        Ok(chat) => Ok(Some((chat, silent))),
+        Err(_) => {
+            bot.send_message(
+                msg.chat.id,
+                "❌ Can't find that chat, is the bot a member of it?",
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Number of songs asked for with `/addrand`, one when it's left out
+pub fn rand_amount(amount: &str) -> Option<usize> {
+    if amount.is_empty() {
+        Some(1)
+    } else {
+        amount.parse().ok()
+    }
+}
+
+pub async fn add_rand(
+    bot: Bot,
+    msg: Message,
+    amount: String,
+    mpd: MpdPool,
+    role: Role,
+    limiter: RateLimiter,
+) -> HandlerResult {
+    let Some(amount) = rand_amount(&amount) else {
         bot.send_message(msg.chat.id, "Invalid number").await?;
         return Ok(());
     };
+    // The rate limit charged at most a full bucket for it
+    let amount = if role < Role::Admin {
+        limiter.cap(amount)
+    } else {
+        amount
+    };
 
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH).unwrap()).unwrap();
-    let mut current = mpd
-        .currentsong()?
-        .unwrap_or_default()
-        .place
-        .unwrap_or_default()
-        .pos as usize;
-    let songs = mpd.listall()?;
-    let songs = songs.choose_multiple(&mut rand::rng(), amount).cloned();
-    for song in songs {
-        mpd.insert(song, current)?;
-        current += 1;
-    }
+    mpd.run(move |conn| {
+        let current = conn
+            .currentsong()?
+            .unwrap_or_default()
+            .place
+            .unwrap_or_default()
+            .pos as usize;
+        let songs = conn.listall()?;
+        let songs = songs.choose_multiple(&mut rand::rng(), amount).cloned();
+        for (pos, song) in (current..).zip(songs) {
+            conn.insert(song, pos)?;
+        }
+        Ok(())
+    })
+    .await?;
 
     bot.send_message(
         msg.chat.id,
@@ -479,15 +1005,14 @@ pub async fn add_rand(bot: Bot, msg: Message, amount: String) -> HandlerResult {
     .await?;
     Ok(())
 }
-pub async fn add_all(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
-    let stats = mpd.stats()?;
-    let all_songs = Song {
-        file: "/".into(),
-        ..Default::default()
-    };
-    mpd.push(all_songs)?;
-    mpd.toggle_pause()?;
+pub async fn add_all(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
+    add_directory(&mpd, "").await?;
+    let stats = mpd
+        .run(|conn| {
+            conn.toggle_pause()?;
+            conn.stats()
+        })
+        .await?;
     bot.send_message(
         msg.chat.id,
         format!("Successfully added {} songs to the queue", stats.songs),
@@ -497,8 +1022,7 @@ pub async fn add_all(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-pub async fn add_file(bot: Bot, msg: Message) -> HandlerResult {
-    let mut mpd = Client::new(UnixStream::connect(MPD_SOCKET_PATH)?)?;
+pub async fn add_file(bot: Bot, msg: Message, mpd: MpdPool) -> HandlerResult {
     {
         let mut tmp = temp_dir();
         tmp.push("tmpc");
@@ -583,9 +1107,12 @@ pub async fn add_file(bot: Bot, msg: Message) -> HandlerResult {
             ..Default::default()
         })
         .await?;
-    let current = mpd.currentsong()?.unwrap_or_default();
-    let pos = current.place.unwrap_or_default().pos as usize;
-    mpd.insert(song, pos + 1)?;
+    mpd.run(move |conn| {
+        let current = conn.currentsong()?.unwrap_or_default();
+        let pos = current.place.unwrap_or_default().pos as usize;
+        conn.insert(&song, pos + 1)
+    })
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,119 @@
+use mpd::error::Error as MpdError;
+use teloxide::types::{ChatId, InlineKeyboardMarkup, MessageId};
+
+use super::{
+    callback_data::{CallbackAction, page_row},
+    callback_state::{CallbackState, CallbackStore, VIEW_SLOT},
+};
+use crate::connection::{MpdPool, quote};
+
+pub const LS_PAGE_SIZE: usize = 10;
+
+/// `/ls` argument as an MPD path, `""` being the root of the music folder
+pub fn normalize(path: &str) -> String {
+    path.trim().trim_matches('/').to_string()
+}
+
+pub fn parent(path: &str) -> String {
+    path.rsplit_once('/')
+        .map(|(parent, _)| parent.to_string())
+        .unwrap_or_default()
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+/// Subdirectories then songs of a directory, with the text of their buttons.
+/// The mpd crate's `lsinfo` skips directories, so the raw command is used.
+pub async fn list_directory(
+    mpd: &MpdPool,
+    path: &str,
+) -> Result<Vec<(String, CallbackState)>, MpdError> {
+    let pairs = mpd.raw_command(format!("lsinfo {}", quote(path)?)).await?;
+    let mut dirs = vec![];
+    let mut files: Vec<(String, String)> = vec![];
+    for (key, val) in pairs {
+        match key.as_str() {
+            "directory" => dirs.push(val),
+            "file" => files.push((file_name(&val).to_string(), val)),
+            "Title" => {
+                if let Some((text, _)) = files.last_mut() {
+                    *text = val;
+                }
+            }
+            _ => {}
+        }
+    }
+    let dirs = dirs.into_iter().map(|path| {
+        (
+            format!("📁 {}", file_name(&path)),
+            CallbackState::Directory { path },
+        )
+    });
+    let files = files
+        .into_iter()
+        .map(|(text, file)| (format!("🎵 {text}"), CallbackState::Song { file }));
+    Ok(dirs.chain(files).collect())
+}
+
+/// Add a directory and everything below it to the queue
+pub async fn add_directory(mpd: &MpdPool, path: &str) -> Result<(), MpdError> {
+    let path = if path.is_empty() { "/" } else { path };
+    mpd.raw_command(format!("add {}", quote(path)?)).await?;
+    Ok(())
+}
+
+/// Render one page of a directory with its keyboard, replacing what the
+/// message stored before with the directory and the entries of the page
+pub fn ls_page(
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+    path: &str,
+    entries: &[(String, CallbackState)],
+    page: u32,
+) -> sled::Result<(String, InlineKeyboardMarkup)> {
+    let pages = entries.len().div_ceil(LS_PAGE_SIZE).max(1) as u32;
+    let page = page.min(pages - 1);
+    let start = page as usize * LS_PAGE_SIZE;
+
+    store.clear(chat, msg)?;
+    let dir = CallbackState::Directory {
+        path: path.to_string(),
+    };
+    store.put(chat, msg, VIEW_SLOT, &dir)?;
+    let mut buttons = vec![];
+    for (slot, (text, entry)) in
+        (VIEW_SLOT + 1..).zip(entries.iter().skip(start).take(LS_PAGE_SIZE))
+    {
+        store.put(chat, msg, slot, entry)?;
+        buttons.push(match entry {
+            CallbackState::Directory { .. } => vec![
+                CallbackAction::LsOpen { slot }.button(text),
+                CallbackAction::LsAdd { slot }.button("➕"),
+            ],
+            _ => vec![CallbackAction::SearchResult { slot }.button(text)],
+        });
+    }
+    buttons.extend(page_row(page, pages, |page| CallbackAction::LsPage {
+        page,
+    }));
+    let add = CallbackAction::LsAdd { slot: VIEW_SLOT }.button("➕ Add this directory");
+    buttons.push(if path.is_empty() {
+        vec![add]
+    } else {
+        vec![CallbackAction::LsUp.button("⬆ Up"), add]
+    });
+
+    let dirs = entries
+        .iter()
+        .filter(|(_, x)| matches!(x, CallbackState::Directory { .. }))
+        .count();
+    let text = format!(
+        "📂 /{path}\n{dirs} folders, {} songs, page {}/{pages}",
+        entries.len() - dirs,
+        page + 1
+    );
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use mpd::error::Error as MpdError;
+use teloxide::types::InlineKeyboardMarkup;
+
+use super::callback_data::CallbackAction;
+use crate::connection::MpdPool;
+
+/// Crossfade lengths the crossfade button cycles through, in seconds
+pub const CROSSFADE_STEPS: [u64; 4] = [0, 2, 5, 10];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Single {
+    Off,
+    On,
+    /// Stop after the current song, then turn single mode off again
+    Oneshot,
+}
+
+/// MPD's playback options, as shown on the `/modes` panel
+#[derive(Clone, Copy, Debug)]
+pub struct Modes {
+    pub repeat: bool,
+    pub random: bool,
+    pub single: Single,
+    pub consume: bool,
+    pub crossfade: u64,
+}
+
+/// A button on the `/modes` panel
+#[derive(Clone, Copy, Debug)]
+pub enum ModeToggle {
+    Repeat,
+    Random,
+    Single,
+    Consume,
+    Crossfade,
+}
+
+impl ModeToggle {
+    pub fn parse(text: &str) -> Option<Self> {
+        Some(match text {
+            "repeat" => ModeToggle::Repeat,
+            "random" => ModeToggle::Random,
+            "single" => ModeToggle::Single,
+            "consume" => ModeToggle::Consume,
+            "xfade" => ModeToggle::Crossfade,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModeToggle::Repeat => "repeat",
+            ModeToggle::Random => "random",
+            ModeToggle::Single => "single",
+            ModeToggle::Consume => "consume",
+            ModeToggle::Crossfade => "xfade",
+        }
+    }
+}
+
+impl Modes {
+    pub async fn fetch(mpd: &MpdPool) -> Result<Self, MpdError> {
+        let status = mpd.run(|conn| conn.status()).await?;
+        // The mpd crate reads "oneshot" as off, so ask for the raw value
+        let single = mpd
+            .raw_command("status".into())
+            .await?
+            .into_iter()
+            .find(|(key, _)| key == "single")
+            .map(|(_, val)| match val.as_str() {
+                "1" => Single::On,
+                "oneshot" => Single::Oneshot,
+                _ => Single::Off,
+            })
+            .unwrap_or(Single::Off);
+
+        Ok(Self {
+            repeat: status.repeat,
+            random: status.random,
+            single,
+            consume: status.consume,
+            crossfade: status.crossfade.unwrap_or_default().as_secs(),
+        })
+    }
+
+    /// Flip one option and return the options as MPD reports them afterwards
+    pub async fn toggle(self, mpd: &MpdPool, toggle: ModeToggle) -> Result<Self, MpdError> {
+        match toggle {
+            ModeToggle::Repeat => mpd.run(move |conn| conn.repeat(!self.repeat)).await?,
+            ModeToggle::Random => mpd.run(move |conn| conn.random(!self.random)).await?,
+            ModeToggle::Consume => mpd.run(move |conn| conn.consume(!self.consume)).await?,
+            ModeToggle::Single => match self.single {
+                Single::Off => mpd.run(|conn| conn.single(true)).await?,
+                Single::On => {
+                    mpd.raw_command("single oneshot".into()).await?;
+                }
+                Single::Oneshot => mpd.run(|conn| conn.single(false)).await?,
+            },
+            ModeToggle::Crossfade => {
+                let next = CROSSFADE_STEPS
+                    .into_iter()
+                    .find(|x| *x > self.crossfade)
+                    .unwrap_or(0);
+                mpd.run(move |conn| conn.crossfade(Duration::from_secs(next)))
+                    .await?
+            }
+        }
+        Self::fetch(mpd).await
+    }
+
+    pub fn text(&self) -> String {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let single = match self.single {
+            Single::Off => "off",
+            Single::On => "on",
+            Single::Oneshot => "oneshot",
+        };
+        format!(
+            "🎛 Playback modes\n\n🔁 Repeat: {}\n🔀 Random: {}\n🔂 Single: {single}\n🍽 Consume: {}\n⏳ Crossfade: {}s",
+            on_off(self.repeat),
+            on_off(self.random),
+            on_off(self.consume),
+            self.crossfade
+        )
+    }
+
+    pub fn keyboard(&self) -> InlineKeyboardMarkup {
+        let flag = |on: bool| if on { "✅" } else { "❌" };
+        let single = match self.single {
+            Single::Off => "❌",
+            Single::On => "✅",
+            Single::Oneshot => "1️⃣",
+        };
+        let button =
+            |text: String, toggle: ModeToggle| CallbackAction::ToggleMode(toggle).button(text);
+        InlineKeyboardMarkup::new(vec![
+            vec![
+                button(
+                    format!("🔁 Repeat {}", flag(self.repeat)),
+                    ModeToggle::Repeat,
+                ),
+                button(
+                    format!("🔀 Random {}", flag(self.random)),
+                    ModeToggle::Random,
+                ),
+            ],
+            vec![
+                button(format!("🔂 Single {single}"), ModeToggle::Single),
+                button(
+                    format!("🍽 Consume {}", flag(self.consume)),
+                    ModeToggle::Consume,
+                ),
+            ],
+            vec![button(
+                format!("⏳ Crossfade {}s", self.crossfade),
+                ModeToggle::Crossfade,
+            )],
+        ])
+    }
+}
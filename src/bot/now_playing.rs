@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{info, warn};
+use mpd::{State, error::Error as MpdError};
+use teloxide::{
+    ApiError, RequestError,
+    prelude::*,
+    types::{InlineKeyboardMarkup, MessageId, ParseMode},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{
+    callback_data::CallbackAction, presenter::SongView, rich_text::Markup, seek::format_time,
+};
+use crate::connection::MpdPool;
+
+/// How long a live message keeps updating unless `TMPC_LIVE_MINUTES` says otherwise
+pub const DEFAULT_LIVE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// MPD only reports changes of state, so progress is refreshed this often in between
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+const PROGRESS_BAR_WIDTH: usize = 12;
+
+/// Buttons of a live message
+#[derive(Clone, Copy, Debug)]
+pub enum PlayerControl {
+    Prev,
+    Toggle,
+    Next,
+}
+
+impl PlayerControl {
+    pub fn parse(text: &str) -> Option<Self> {
+        Some(match text {
+            "prev" => PlayerControl::Prev,
+            "toggle" => PlayerControl::Toggle,
+            "next" => PlayerControl::Next,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlayerControl::Prev => "prev",
+            PlayerControl::Toggle => "toggle",
+            PlayerControl::Next => "next",
+        }
+    }
+}
+
+/// The `/np live` message of each chat. Starting a new one in a chat stops
+/// the previous one.
+#[derive(Clone)]
+pub struct LiveMessages {
+    timeout: Duration,
+    chats: Arc<Mutex<HashMap<ChatId, MessageId>>>,
+}
+
+impl LiveMessages {
+    pub fn from_env() -> Self {
+        let timeout = env::var("TMPC_LIVE_MINUTES")
+            .ok()
+            .and_then(|x| {
+                let parsed = x.trim().parse::<u64>().ok().filter(|x| *x > 0);
+                if parsed.is_none() {
+                    warn!("Ignoring invalid TMPC_LIVE_MINUTES: {x}");
+                }
+                parsed
+            })
+            .map(|x| Duration::from_secs(x * 60))
+            .unwrap_or(DEFAULT_LIVE_TIMEOUT);
+
+        Self {
+            timeout,
+            chats: Default::default(),
+        }
+    }
+
+    fn is_live(&self, chat: ChatId, msg: MessageId) -> bool {
+        self.chats.lock().unwrap().get(&chat) == Some(&msg)
+    }
+
+    /// Keep `msg` updated with the current song until the timeout or until
+    /// another live message replaces it
+    pub fn start(&self, bot: Bot, mpd: MpdPool, chat: ChatId, msg: MessageId) {
+        self.chats.lock().unwrap().insert(chat, msg);
+        info!("Started live message in {chat}");
+        tokio::spawn(self.clone().run(bot, mpd, chat, msg));
+    }
+
+    async fn run(self, bot: Bot, mpd: MpdPool, chat: ChatId, msg: MessageId) {
+        let mut changes = mpd.watch();
+        let deadline = tokio::time::sleep(self.timeout);
+        tokio::pin!(deadline);
+        let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                change = changes.recv() => if let Err(RecvError::Closed) = change {
+                    break;
+                },
+                _ = progress.tick() => {}
+            }
+            if !self.is_live(chat, msg) {
+                break;
+            }
+            let text = match live_text(&mpd).await {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!("Can't update live message: {}", e);
+                    continue;
+                }
+            };
+            match bot
+                .edit_message_text(chat, msg, text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(controls_keyboard())
+                .await
+            {
+                Err(RequestError::Api(ApiError::MessageNotModified)) | Ok(_) => {}
+                // Most likely deleted, nothing left to update
+                Err(e) => {
+                    warn!("Stopping live message in {chat}: {}", e);
+                    break;
+                }
+            }
+        }
+
+        {
+            let mut chats = self.chats.lock().unwrap();
+            if chats.get(&chat) == Some(&msg) {
+                chats.remove(&chat);
+            }
+        }
+        info!("Stopped live message in {chat}");
+        let text = live_text(&mpd).await.unwrap_or_default();
+        let _ = bot
+            .edit_message_text(chat, msg, format!("{text}\n\n💤 No longer live"))
+            .parse_mode(ParseMode::Html)
+            .await;
+    }
+}
+
+/// Current song with its progress, as shown in live messages, in HTML
+pub async fn live_text(mpd: &MpdPool) -> Result<String, MpdError> {
+    let (status, song) = mpd
+        .run(|conn| Ok((conn.status()?, conn.currentsong()?)))
+        .await?;
+    let Some(song) = song.filter(|_| status.state != State::Stop) else {
+        return Ok("⏹ Nothing playing".into());
+    };
+    let icon = if status.state == State::Play {
+        "▶️"
+    } else {
+        "⏸"
+    };
+    let elapsed = status.elapsed.unwrap_or_default();
+    let progress = match status.duration.filter(|x| !x.is_zero()) {
+        Some(total) => format!(
+            "{} {} {}",
+            Markup::Html.code(&format_time(elapsed)),
+            progress_bar(elapsed, total),
+            Markup::Html.code(&format_time(total))
+        ),
+        None => Markup::Html.code(&format_time(elapsed)),
+    };
+    Ok(format!(
+        "{}\n\n{icon} {progress}",
+        SongView::new(&song).markup(Markup::Html).full()
+    ))
+}
+
+fn progress_bar(elapsed: Duration, total: Duration) -> String {
+    let filled = ((elapsed.as_secs_f64() / total.as_secs_f64()) * PROGRESS_BAR_WIDTH as f64)
+        .round()
+        .clamp(0.0, PROGRESS_BAR_WIDTH as f64) as usize;
+    "▓".repeat(filled) + &"░".repeat(PROGRESS_BAR_WIDTH - filled)
+}
+
+pub fn controls_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        CallbackAction::Player(PlayerControl::Prev).button("⏮"),
+        CallbackAction::Player(PlayerControl::Toggle).button("⏯"),
+        CallbackAction::Player(PlayerControl::Next).button("⏭"),
+    ]])
+}
@@ -0,0 +1,61 @@
+use mpd::Playlist;
+use teloxide::types::InlineKeyboardMarkup;
+
+use super::callback_data::CallbackAction;
+
+/// One row per playlist: replace the queue with it, or append it
+pub fn playlists_keyboard(playlists: &[Playlist]) -> InlineKeyboardMarkup {
+    let rows = playlists
+        .iter()
+        // Names that don't fit in the callback data can still be used with /load
+        .filter_map(|x| {
+            let name = x.name.clone();
+            Some(vec![
+                CallbackAction::LoadPlaylist { name: name.clone() }
+                    .try_button(format!("▶ {}", x.name))?,
+                CallbackAction::AppendPlaylist { name }.try_button("➕ append")?,
+            ])
+        })
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Split `/renameplaylist` arguments, either `old | new` or `old new`
+pub fn parse_rename(args: &str) -> Option<(String, String)> {
+    let (from, to) = match args.split_once('|') {
+        Some(t) => t,
+        None => {
+            let mut words = args.split_whitespace();
+            let t = (words.next()?, words.next()?);
+            if words.next().is_some() {
+                return None;
+            }
+            t
+        }
+    };
+    let (from, to) = (from.trim(), to.trim());
+    (!from.is_empty() && !to.is_empty()).then(|| (from.to_string(), to.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_renames() {
+        let rename = |from: &str, to: &str| Some((from.to_string(), to.to_string()));
+        assert_eq!(parse_rename("old new"), rename("old", "new"));
+        assert_eq!(
+            parse_rename(" road trip | summer 2024 "),
+            rename("road trip", "summer 2024")
+        );
+        assert_eq!(parse_rename("a|b|c"), rename("a", "b|c"));
+    }
+
+    #[test]
+    fn rejects_invalid_renames() {
+        for args in ["", "old", "old new extra", "old |", "| new", "  |  "] {
+            assert!(parse_rename(args).is_none(), "{args} shouldn't parse");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use mpd::Song;
+
+use super::{rich_text::Markup, seek::format_time};
+
+/// Tags shown on their own line by `SongView::full`
+#[derive(Clone, Copy, Debug)]
+pub enum Tag {
+    Year,
+    Genre,
+    Track,
+}
+
+impl Tag {
+    fn line(self, song: &Song) -> Option<String> {
+        Some(match self {
+            // Dates are like 2001 or 2001-05-14, the year is enough
+            Tag::Year => format!("📅 {}", tag(song, "Date")?.split('-').next()?),
+            Tag::Genre => format!("🏷 {}", tag(song, "Genre")?),
+            // Tracks can be like 3/12
+            Tag::Track => format!("💽 Track {}", tag(song, "Track")?.split('/').next()?),
+        })
+    }
+}
+
+/// Value of a tag of a song, whatever the case of its name
+pub fn tag<'a>(song: &'a Song, name: &str) -> Option<&'a str> {
+    song.tags
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, val)| val.as_str())
+        .filter(|x| !x.is_empty())
+}
+
+/// Title of a song, or its file name when it isn't tagged
+pub fn title(song: &Song) -> &str {
+    song.title
+        .as_deref()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| {
+            song.file
+                .rsplit_once('/')
+                .map_or(&song.file, |(_, name)| name)
+        })
+}
+
+pub fn artist(song: &Song) -> &str {
+    song.artist
+        .as_deref()
+        .filter(|x| !x.is_empty())
+        .unwrap_or("Unknown")
+}
+
+/// A song rendered for a message or a button. Pick what to show with the
+/// builder methods, then render it with `compact` or `full`.
+#[derive(Clone, Copy)]
+pub struct SongView<'a> {
+    song: &'a Song,
+    markup: Markup,
+    duration: bool,
+    position: Option<(Duration, Duration)>,
+    tags: &'a [Tag],
+}
+
+impl<'a> SongView<'a> {
+    pub fn new(song: &'a Song) -> Self {
+        Self {
+            song,
+            markup: Markup::Plain,
+            duration: false,
+            position: None,
+            tags: &[],
+        }
+    }
+
+    pub fn markup(self, markup: Markup) -> Self {
+        Self { markup, ..self }
+    }
+
+    /// Show the duration of the song
+    pub fn with_duration(self) -> Self {
+        Self {
+            duration: true,
+            ..self
+        }
+    }
+
+    /// Show how far into the song playback is, as `elapsed / total`
+    pub fn with_position(self, elapsed: Duration, total: Duration) -> Self {
+        Self {
+            position: Some((elapsed, total)),
+            ..self
+        }
+    }
+
+    pub fn with_tags(self, tags: &'a [Tag]) -> Self {
+        Self { tags, ..self }
+    }
+
+    fn time(&self) -> Option<String> {
+        if let Some((elapsed, total)) = self.position {
+            return Some(format!("{} / {}", format_time(elapsed), format_time(total)));
+        }
+        self.duration
+            .then(|| self.song.duration.map(format_time).unwrap_or("-:--".into()))
+    }
+
+    /// One line, `title - artist (3:20)`, for lists and buttons
+    pub fn compact(&self) -> String {
+        let m = self.markup;
+        let mut text = format!(
+            "{} - {}",
+            m.bold(title(self.song)),
+            m.escape(artist(self.song))
+        );
+        if let Some(time) = self.time() {
+            text += &format!(" ({})", m.code(&time));
+        }
+        text
+    }
+
+    /// One line per field, for messages about a single song
+    pub fn full(&self) -> String {
+        let m = self.markup;
+        let mut lines = vec![
+            format!("🎵 {}", m.bold(title(self.song))),
+            format!("👤 {}", m.escape(artist(self.song))),
+        ];
+        if let Some(album) = tag(self.song, "Album") {
+            lines.push(format!("💿 {}", m.escape(album)));
+        }
+        lines.extend(
+            self.tags
+                .iter()
+                .filter_map(|x| x.line(self.song))
+                .map(|x| m.escape(&x)),
+        );
+        if let Some(time) = self.time() {
+            lines.push(format!("⏱ {}", m.code(&time)));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song() -> Song {
+        Song {
+            file: "Rock/AC & DC/back in black.flac".into(),
+            title: Some("Back <in> Black".into()),
+            artist: Some(r#"AC & "DC""#.into()),
+            duration: Some(Duration::from_secs(255)),
+            tags: vec![
+                ("Album".into(), "<Back> & Black".into()),
+                ("date".into(), "1980-07-25".into()),
+                ("Genre".into(), "Hard <Rock>".into()),
+                ("Track".into(), "1/10".into()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compact() {
+        let song = song();
+        let view = SongView::new(&song).with_duration();
+        assert_eq!(
+            view.markup(Markup::Html).compact(),
+            "<b>Back &lt;in&gt; Black</b> - AC &amp; &quot;DC&quot; (<code>4:15</code>)"
+        );
+        assert_eq!(view.compact(), r#"Back <in> Black - AC & "DC" (4:15)"#);
+    }
+
+    #[test]
+    fn full() {
+        let song = song();
+        let view = SongView::new(&song)
+            .markup(Markup::Html)
+            .with_position(Duration::from_secs(61), Duration::from_secs(255))
+            .with_tags(&[Tag::Year, Tag::Genre, Tag::Track]);
+        assert_eq!(
+            view.full(),
+            [
+                "🎵 <b>Back &lt;in&gt; Black</b>",
+                "👤 AC &amp; &quot;DC&quot;",
+                "💿 &lt;Back&gt; &amp; Black",
+                "📅 1980",
+                "🏷 Hard &lt;Rock&gt;",
+                "💽 Track 1",
+                "⏱ <code>1:01 / 4:15</code>",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn untagged_songs() {
+        let song = Song {
+            file: "Rock/untitled <1>.mp3".into(),
+            ..Default::default()
+        };
+        let view = SongView::new(&song).markup(Markup::Html);
+        assert_eq!(
+            view.with_duration().compact(),
+            "<b>untitled &lt;1&gt;.mp3</b> - Unknown (<code>-:--</code>)"
+        );
+        assert_eq!(view.full(), "🎵 <b>untitled &lt;1&gt;.mp3</b>\n👤 Unknown");
+    }
+}
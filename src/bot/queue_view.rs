@@ -0,0 +1,83 @@
+use mpd::error::Error as MpdError;
+use teloxide::types::InlineKeyboardMarkup;
+
+use super::{
+    callback_data::{CallbackAction, page_row},
+    presenter::SongView,
+    rich_text::Markup,
+};
+use crate::connection::MpdPool;
+
+pub const QUEUE_PAGE_SIZE: u32 = 10;
+
+/// Render one page of the queue with its navigation keyboard. Without a page,
+/// the one holding the current song is shown
+pub async fn queue_page(
+    mpd: &MpdPool,
+    page: Option<u32>,
+) -> Result<(String, InlineKeyboardMarkup), MpdError> {
+    let status = mpd.run(|conn| conn.status()).await?;
+    let queue_len = status.queue_len;
+    let current = status.song.map(|x| x.pos);
+    let pages = queue_len.div_ceil(QUEUE_PAGE_SIZE).max(1);
+    let page = page
+        .unwrap_or(current.unwrap_or_default() / QUEUE_PAGE_SIZE)
+        .min(pages - 1);
+
+    let start = page * QUEUE_PAGE_SIZE;
+    let end = (start + QUEUE_PAGE_SIZE).min(queue_len);
+    let songs = if start < end {
+        mpd.run(move |conn| conn.songs(start..end)).await?
+    } else {
+        vec![]
+    };
+
+    let mut queue_formatted = songs
+        .iter()
+        .map(|f| {
+            let pos = f.place.unwrap_or_default().pos;
+            let icon = if Some(pos) == current {
+                "▶️"
+            } else {
+                "🎵"
+            };
+            format!(
+                "{}. {icon} {}",
+                pos + 1,
+                SongView::new(f)
+                    .markup(Markup::Html)
+                    .with_duration()
+                    .compact()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    if queue_formatted.is_empty() {
+        queue_formatted = "No song in queue".into();
+    }
+    let text = format!(
+        "🎛Queue length: {queue_len} · page {}/{pages}\n\n{queue_formatted}",
+        page + 1
+    );
+
+    let mut buttons = songs
+        .iter()
+        .filter_map(|f| f.place)
+        .map(|place| {
+            let song = place.id;
+            let mut row =
+                vec![CallbackAction::PlayId { song }.button(format!("▶ {}", place.pos + 1))];
+            // The current song can't be played next
+            if Some(place.pos) != current {
+                row.push(CallbackAction::QueuePlayNext { song, page }.button("⬆ play next"));
+            }
+            row.push(CallbackAction::QueueRemove { song, page }.button("❌ remove"));
+            row
+        })
+        .collect::<Vec<_>>();
+    buttons.extend(page_row(page, pages, |page| CallbackAction::QueuePage {
+        page,
+    }));
+
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
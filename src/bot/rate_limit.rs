@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info};
+use teloxide::{
+    prelude::*,
+    types::{ReplyParameters, UserId},
+};
+
+type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+pub const DEFAULT_BURST: f64 = 5.0;
+pub const DEFAULT_PER_MINUTE: f64 = 6.0;
+
+/// How long a user has to wait before they're allowed to queue songs again
+#[derive(Clone, Copy, Debug)]
+pub struct RetryAfter(pub Duration);
+
+impl RetryAfter {
+    pub fn text(&self) -> String {
+        format!(
+            "🐢 Slow down! You can add more songs in {}s",
+            self.0.as_secs_f64().ceil() as u64
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: SystemTime,
+}
+
+impl Bucket {
+    fn to_bytes(self) -> [u8; 16] {
+        let millis = self
+            .updated
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.tokens.to_be_bytes());
+        bytes[8..].copy_from_slice(&millis.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let tokens = f64::from_be_bytes(bytes.get(..8)?.try_into().ok()?);
+        let millis = u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?);
+        Some(Self {
+            tokens,
+            updated: UNIX_EPOCH + Duration::from_millis(millis),
+        })
+    }
+}
+
+/// Token bucket per user for commands that add songs to the queue, configured with:
+///
+/// - `TMPC_RATE_LIMIT_BURST` — how many songs can be queued in a row (default 5), which
+///   is also the most a non-admin can add at once with `/addrand`
+/// - `TMPC_RATE_LIMIT_PER_MINUTE` — how fast the bucket refills (default 6), `0` disables the limit
+/// - `TMPC_RATE_LIMIT_PERSIST` — set to `1` to keep buckets in the DB across restarts
+#[derive(Clone)]
+pub struct RateLimiter {
+    burst: f64,
+    per_second: f64,
+    buckets: Arc<Mutex<HashMap<UserId, Bucket>>>,
+    tree: Option<sled::Tree>,
+}
+
+impl RateLimiter {
+    pub fn from_env(db: &sled::Db) -> Self {
+        let var = |name: &str, default: f64| {
+            env::var(name)
+                .ok()
+                .and_then(|x| x.parse::<f64>().ok())
+                .filter(|x| x.is_finite() && *x >= 0.0)
+                .unwrap_or(default)
+        };
+        let burst = var("TMPC_RATE_LIMIT_BURST", DEFAULT_BURST).max(1.0);
+        let per_minute = var("TMPC_RATE_LIMIT_PER_MINUTE", DEFAULT_PER_MINUTE);
+        let tree = match env::var("TMPC_RATE_LIMIT_PERSIST").as_deref() {
+            Ok("1") | Ok("true") => match db.open_tree("rate_limits") {
+                Ok(tree) => Some(tree),
+                Err(e) => {
+                    error!("Can't open rate limit tree, keeping it in memory: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        if per_minute == 0.0 {
+            info!("Rate limiting disabled");
+        }
+
+        Self {
+            burst,
+            per_second: per_minute / 60.0,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            tree,
+        }
+    }
+
+    /// Most songs a single command can be charged for
+    pub fn burst(&self) -> u32 {
+        self.burst as u32
+    }
+
+    /// Most of `songs` that can be added at once, the burst unless the limit
+    /// is disabled
+    pub fn cap(&self, songs: usize) -> usize {
+        if self.per_second == 0.0 {
+            songs
+        } else {
+            songs.min(self.burst as usize)
+        }
+    }
+
+    /// Spend one token per song from `user`'s bucket, or tell when there will
+    /// be enough. More songs than the burst cost a full bucket.
+    pub fn check(&self, user: UserId, songs: u32) -> Result<(), RetryAfter> {
+        if self.per_second == 0.0 {
+            return Ok(());
+        }
+        let now = SystemTime::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(user).or_insert_with(|| {
+            self.load(user).unwrap_or(Bucket {
+                tokens: self.burst,
+                updated: now,
+            })
+        });
+
+        let elapsed = now.duration_since(bucket.updated).unwrap_or_default();
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        bucket.updated = now;
+        let cost = (songs as f64).min(self.burst);
+        let res = if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            let wait = (cost - bucket.tokens) / self.per_second;
+            Err(RetryAfter(Duration::from_secs_f64(wait)))
+        };
+        self.store(user, *bucket);
+        res
+    }
+
+    fn load(&self, user: UserId) -> Option<Bucket> {
+        let tree = self.tree.as_ref()?;
+        let bytes = tree.get(user.0.to_be_bytes()).ok()??;
+        Bucket::from_bytes(&bytes)
+    }
+
+    fn store(&self, user: UserId, bucket: Bucket) {
+        let Some(tree) = &self.tree else {
+            return;
+        };
+        if let Err(e) = tree.insert(user.0.to_be_bytes(), &bucket.to_bytes()) {
+            error!("{}", e);
+        }
+    }
+}
+
+pub async fn slow_down(bot: Bot, msg: Message, retry: RetryAfter) -> HandlerResult {
+    bot.send_message(msg.chat.id, retry.text())
+        .reply_parameters(ReplyParameters {
+            message_id: msg.id,
+            ..Default::default()
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+/// Telegram refuses messages longer than this many UTF-16 code units
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// How text is going to be parsed by Telegram. Everything with formatting is
+/// sent with `ParseMode::Html`, and anything that comes from MPD, users or
+/// errors goes through these helpers so it can't break the markup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Markup {
+    /// Text sent without a parse mode, or used in buttons and toasts
+    #[default]
+    Plain,
+    Html,
+}
+
+impl Markup {
+    pub fn escape(self, text: &str) -> String {
+        match self {
+            Markup::Plain => text.to_string(),
+            Markup::Html => {
+                let mut escaped = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c {
+                        '&' => escaped.push_str("&amp;"),
+                        '<' => escaped.push_str("&lt;"),
+                        '>' => escaped.push_str("&gt;"),
+                        '"' => escaped.push_str("&quot;"),
+                        _ => escaped.push(c),
+                    }
+                }
+                escaped
+            }
+        }
+    }
+
+    pub fn bold(self, text: &str) -> String {
+        match self {
+            Markup::Plain => text.to_string(),
+            Markup::Html => format!("<b>{}</b>", self.escape(text)),
+        }
+    }
+
+    /// Monospace text, for durations and commands
+    pub fn code(self, text: &str) -> String {
+        match self {
+            Markup::Plain => text.to_string(),
+            Markup::Html => format!("<code>{}</code>", self.escape(text)),
+        }
+    }
+
+    /// Monospace block, for error output
+    pub fn pre(self, text: &str) -> String {
+        match self {
+            Markup::Plain => text.to_string(),
+            Markup::Html => format!("<pre>{}</pre>", self.escape(text)),
+        }
+    }
+
+    pub fn link(self, url: &str, text: &str) -> String {
+        match self {
+            Markup::Plain => format!("{text} ({url})"),
+            Markup::Html => format!("<a href=\"{}\">{}</a>", self.escape(url), self.escape(text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"Tom & Jerry <live> "remix""#;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            Markup::Html.escape(TEXT),
+            "Tom &amp; Jerry &lt;live&gt; &quot;remix&quot;"
+        );
+        assert_eq!(Markup::Plain.escape(TEXT), TEXT);
+    }
+
+    #[test]
+    fn wraps_escaped_text() {
+        let html = Markup::Html;
+        assert_eq!(html.bold("a<b"), "<b>a&lt;b</b>");
+        assert_eq!(html.code("1 > 0"), "<code>1 &gt; 0</code>");
+        assert_eq!(html.pre("x & y"), "<pre>x &amp; y</pre>");
+        for text in [
+            Markup::Plain.bold(TEXT),
+            Markup::Plain.code(TEXT),
+            Markup::Plain.pre(TEXT),
+        ] {
+            assert_eq!(text, TEXT);
+        }
+    }
+
+    #[test]
+    fn links() {
+        let url = r#"https://example.com/?q="a"&b=<c>"#;
+        assert_eq!(
+            Markup::Html.link(url, "<me>"),
+            r#"<a href="https://example.com/?q=&quot;a&quot;&amp;b=&lt;c&gt;">&lt;me&gt;</a>"#
+        );
+        assert_eq!(Markup::Plain.link(url, "me"), format!("me ({url})"));
+    }
+}
@@ -0,0 +1,217 @@
+use mpd::{
+    Query, Song, Term,
+    error::{Error as MpdError, ErrorCode},
+    search::Window,
+};
+
+use crate::connection::{MpdClient, MpdPool, quote};
+
+/// `field:` prefixes understood by `/search`, and the MPD tag each one filters on
+pub const SEARCH_FIELDS: [(&str, &str); 10] = [
+    ("artist", "Artist"),
+    ("albumartist", "AlbumArtist"),
+    ("album", "Album"),
+    ("title", "Title"),
+    ("year", "Date"),
+    ("date", "Date"),
+    ("genre", "Genre"),
+    ("composer", "Composer"),
+    ("track", "Track"),
+    ("file", "file"),
+];
+
+/// A parsed `/search` query such as `artist:radiohead album:"ok computer" creep`.
+///
+/// Words without a known field are searched in any tag. With `--exact` (or
+/// `-e`), tags have to match exactly instead of containing the text, case
+/// sensitively.
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub filters: Vec<(&'static str, String)>,
+    pub free_text: Vec<String>,
+    pub exact: bool,
+}
+
+impl SearchQuery {
+    pub fn parse(text: &str) -> Self {
+        let mut query = Self::default();
+        for token in tokenize(text) {
+            if token == "--exact" || token == "-e" {
+                query.exact = true;
+                continue;
+            }
+            let field = token.split_once(':').and_then(|(name, value)| {
+                SEARCH_FIELDS
+                    .iter()
+                    .find(|(field, _)| field.eq_ignore_ascii_case(name))
+                    .map(|(_, tag)| (*tag, value))
+            });
+            match field {
+                Some((tag, value)) if !value.is_empty() => {
+                    query.filters.push((tag, value.to_string()))
+                }
+                _ => query.free_text.push(token),
+            }
+        }
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.free_text.is_empty()
+    }
+
+    fn to_mpd(&self) -> Query<'_> {
+        let mut query = Query::new();
+        for (tag, value) in &self.filters {
+            let term = match *tag {
+                "file" => Term::File,
+                tag => Term::Tag(tag.into()),
+            };
+            query.and(term, value.as_str());
+        }
+        if !self.free_text.is_empty() {
+            query.and(Term::Any, self.free_text.join(" "));
+        }
+        query
+    }
+
+    /// Arguments of the query for raw commands, like the mpd crate sends them
+    fn to_args(&self) -> Result<String, MpdError> {
+        let mut args = self
+            .filters
+            .iter()
+            .map(|(tag, value)| Ok(format!("{tag} {}", quote(value)?)))
+            .collect::<Result<Vec<_>, MpdError>>()?;
+        if !self.free_text.is_empty() {
+            args.push(format!("any {}", quote(&self.free_text.join(" "))?));
+        }
+        Ok(args.join(" "))
+    }
+
+    /// Number of matching songs, counted by MPD. `searchcount` only exists
+    /// since MPD 0.24, older versions send every match over to be counted.
+    pub async fn count(&self, mpd: &MpdPool) -> Result<u32, MpdError> {
+        let command = if self.exact { "count" } else { "searchcount" };
+        match mpd
+            .raw_command(format!("{command} {}", self.to_args()?))
+            .await
+        {
+            Ok(pairs) => Ok(pairs
+                .iter()
+                .find(|(key, _)| key == "songs")
+                .and_then(|(_, val)| val.parse().ok())
+                .unwrap_or_default()),
+            Err(MpdError::Server(e)) if e.code == ErrorCode::UnknownCmd => {
+                let query = self.clone();
+                let songs = mpd
+                    .run(move |conn| query.run(conn, Window::from(None)))
+                    .await?;
+                Ok(songs.len() as u32)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add every matching song to the queue in a single command
+    pub async fn add_all(&self, mpd: &MpdPool) -> Result<(), MpdError> {
+        let command = if self.exact { "findadd" } else { "searchadd" };
+        mpd.raw_command(format!("{command} {}", self.to_args()?))
+            .await?;
+        Ok(())
+    }
+
+    /// Run the query with MPD's `find` for exact matches, `search` otherwise
+    pub fn run(&self, conn: &mut MpdClient, window: Window) -> Result<Vec<Song>, MpdError> {
+        if self.exact {
+            conn.find(&self.to_mpd(), window)
+        } else {
+            conn.search(&self.to_mpd(), window)
+        }
+    }
+}
+
+/// Split on whitespace, keeping "quoted parts" together
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fields_and_free_text() {
+        let query = SearchQuery::parse(r#"artist:radiohead album:"ok computer" creep"#);
+        assert_eq!(
+            query.filters,
+            [
+                ("Artist", "radiohead".into()),
+                ("Album", "ok computer".into())
+            ]
+        );
+        assert_eq!(query.free_text, ["creep"]);
+        assert!(!query.exact);
+    }
+
+    #[test]
+    fn parses_exact_and_aliases() {
+        let query = SearchQuery::parse("--exact Year:1997 -e GENRE:Rock");
+        assert!(query.exact);
+        assert_eq!(
+            query.filters,
+            [("Date", "1997".into()), ("Genre", "Rock".into())]
+        );
+        assert!(query.free_text.is_empty());
+    }
+
+    #[test]
+    fn unknown_or_empty_fields_are_free_text() {
+        let query = SearchQuery::parse("mood:happy title: enter sandman");
+        assert!(query.filters.is_empty());
+        assert_eq!(
+            query.free_text,
+            ["mood:happy", "title:", "enter", "sandman"]
+        );
+    }
+
+    #[test]
+    fn empty_queries() {
+        assert!(SearchQuery::parse("").is_empty());
+        assert!(SearchQuery::parse("  --exact ").is_empty());
+        assert!(SearchQuery::parse(r#""""#).is_empty());
+    }
+
+    #[test]
+    fn quotes_raw_arguments() {
+        let query = SearchQuery {
+            filters: vec![("Title", r#"say "hi" \o/"#.into())],
+            free_text: vec!["foo".into(), "bar".into()],
+            exact: false,
+        };
+        assert_eq!(
+            query.to_args().unwrap(),
+            r#"Title "say \"hi\" \\o/" any "foo bar""#
+        );
+        // A newline would end the command and let the rest run as another one
+        let query = SearchQuery::parse("artist:\"foo\nclear\"");
+        assert_eq!(query.filters, [("Artist", "foo\nclear".into())]);
+        assert!(query.to_args().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+
+use mpd::search::Window;
+use teloxide::types::{ChatId, InlineKeyboardMarkup, MessageId};
+
+use super::{
+    callback_data::{CallbackAction, page_row},
+    callback_state::{CallbackState, CallbackStore, VIEW_SLOT},
+    presenter::SongView,
+    search_query::SearchQuery,
+};
+use crate::connection::MpdPool;
+
+pub const SEARCH_PAGE_SIZE: u32 = 10;
+
+/// Remember the search behind a results message so its pages can be fetched again
+pub fn save_search(
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+    total: u32,
+    query: String,
+) -> sled::Result<()> {
+    store.put(
+        chat,
+        msg,
+        VIEW_SLOT,
+        &CallbackState::Search { total, query },
+    )
+}
+
+/// Total and query of the search behind a results message, `None` once expired
+pub fn load_search(
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+) -> sled::Result<Option<(u32, String)>> {
+    Ok(match store.get(chat, msg, VIEW_SLOT)? {
+        Some(CallbackState::Search { total, query }) => Some((total, query)),
+        _ => None,
+    })
+}
+
+/// File of the search result or browsed track behind a button, `None` once expired
+pub fn search_result(
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+    slot: u16,
+) -> sled::Result<Option<String>> {
+    Ok(match store.get(chat, msg, slot)? {
+        Some(CallbackState::Song { file }) => Some(file),
+        _ => None,
+    })
+}
+
+/// Run the search for one page of results and render it with its keyboard,
+/// storing each result under a slot of the message
+pub async fn search_page(
+    mpd: &MpdPool,
+    store: &CallbackStore,
+    chat: ChatId,
+    msg: MessageId,
+    total: u32,
+    query: &str,
+    page: u32,
+) -> Result<(String, InlineKeyboardMarkup), Box<dyn Error + Send + Sync>> {
+    let pages = total.div_ceil(SEARCH_PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let start = page * SEARCH_PAGE_SIZE;
+    let end = start + SEARCH_PAGE_SIZE;
+    let query = SearchQuery::parse(query);
+    let songs = mpd
+        .run(move |conn| query.run(conn, Window::from((start, end))))
+        .await?;
+
+    let mut buttons = vec![];
+    for (slot, f) in (VIEW_SLOT + 1..).zip(songs) {
+        let text = SongView::new(&f).compact();
+        store.put(chat, msg, slot, &CallbackState::Song { file: f.file })?;
+        buttons.push(vec![CallbackAction::SearchResult { slot }.button(text)]);
+    }
+    buttons.extend(page_row(page, pages, |page| CallbackAction::SearchPage {
+        page,
+    }));
+    buttons.push(vec![
+        CallbackAction::AddAllResults.button(format!("➕ Add all {total} results")),
+    ]);
+
+    let text = format!(
+        "{total} results found, page {}/{pages}. Tap on a song to add it:",
+        page + 1
+    );
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// A position in the current track requested with `/seek`
+#[derive(Clone, Copy, Debug)]
+pub enum SeekTarget {
+    At(Duration),
+    Forward(Duration),
+    Back(Duration),
+    /// Share of the track's duration, between 0 and 100
+    Percent(f64),
+}
+
+impl SeekTarget {
+    /// Parse `1:30`, `90`, `+30`, `-15` or `50%`
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if let Some(percent) = text.strip_suffix('%') {
+            let percent = percent.trim().parse::<f64>().ok()?;
+            return (0.0..=100.0)
+                .contains(&percent)
+                .then_some(SeekTarget::Percent(percent));
+        }
+        if let Some(time) = text.strip_prefix('+') {
+            return parse_time(time).map(SeekTarget::Forward);
+        }
+        if let Some(time) = text.strip_prefix('-') {
+            return parse_time(time).map(SeekTarget::Back);
+        }
+        parse_time(text).map(SeekTarget::At)
+    }
+
+    /// Position to seek to, kept inside the track
+    pub fn resolve(self, elapsed: Duration, duration: Duration) -> Duration {
+        let pos = match self {
+            SeekTarget::At(x) => x,
+            SeekTarget::Forward(x) => elapsed.saturating_add(x),
+            SeekTarget::Back(x) => elapsed.saturating_sub(x),
+            SeekTarget::Percent(x) => duration.mul_f64(x / 100.0),
+        };
+        pos.min(duration)
+    }
+}
+
+/// Parse `ss`, `mm:ss` or `hh:mm:ss`, `None` if it doesn't fit in a `u64`
+fn parse_time(text: &str) -> Option<Duration> {
+    let mut secs: u64 = 0;
+    for part in text.trim().split(':') {
+        secs = secs.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// Format as `m:ss`, or `h:mm:ss` for long tracks
+pub fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(x: u64) -> Duration {
+        Duration::from_secs(x)
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert!(matches!(SeekTarget::parse("1:30"), Some(SeekTarget::At(x)) if x == secs(90)));
+        assert!(matches!(SeekTarget::parse(" 90 "), Some(SeekTarget::At(x)) if x == secs(90)));
+        assert!(matches!(SeekTarget::parse("1:02:03"), Some(SeekTarget::At(x)) if x == secs(3723)));
+        assert!(matches!(SeekTarget::parse("+30"), Some(SeekTarget::Forward(x)) if x == secs(30)));
+        assert!(matches!(SeekTarget::parse("-15"), Some(SeekTarget::Back(x)) if x == secs(15)));
+        assert!(matches!(SeekTarget::parse("50%"), Some(SeekTarget::Percent(x)) if x == 50.0));
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        for text in ["", "abc", "1:", ":30", "101%", "-1%", "nan%", "1.5", "+-3"] {
+            assert!(SeekTarget::parse(text).is_none(), "{text} shouldn't parse");
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_times() {
+        assert!(SeekTarget::parse("999999999999999999:0").is_none());
+        assert!(SeekTarget::parse("18446744073709551615:0:0").is_none());
+        assert!(SeekTarget::parse("+18446744073709551616").is_none());
+    }
+
+    #[test]
+    fn resolves_inside_the_track() {
+        let (elapsed, duration) = (secs(60), secs(200));
+        let resolve = |text| SeekTarget::parse(text).unwrap().resolve(elapsed, duration);
+        assert_eq!(resolve("+30"), secs(90));
+        assert_eq!(resolve("-90"), secs(0));
+        assert_eq!(resolve("10:00"), duration);
+        assert_eq!(resolve("50%"), secs(100));
+        assert_eq!(resolve("+18446744073709551615"), duration);
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(format_time(secs(5)), "0:05");
+        assert_eq!(format_time(secs(754)), "12:34");
+        assert_eq!(format_time(secs(3723)), "1:02:03");
+    }
+}
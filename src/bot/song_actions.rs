@@ -0,0 +1,72 @@
+use mpd::{Id, Playlist, Song, error::Error as MpdError};
+use teloxide::types::InlineKeyboardMarkup;
+
+use super::callback_data::CallbackAction;
+use crate::connection::MpdClient;
+
+/// What to do with a song picked from search results
+#[derive(Clone, Debug)]
+pub enum SongAction {
+    PlayNext,
+    Append,
+    PlayNow,
+    AddToPlaylist(String),
+}
+
+impl SongAction {
+    pub fn apply(&self, conn: &mut MpdClient, song: Song) -> Result<(), MpdError> {
+        let current = conn.currentsong()?.and_then(|x| x.place);
+        match self {
+            SongAction::PlayNext => match current {
+                Some(current) => conn.insert(song, current.pos as usize + 1).map(|_| ()),
+                None => conn.push(song).map(|_| ()),
+            },
+            SongAction::Append => conn.push(song).map(|_| ()),
+            SongAction::PlayNow => {
+                let id = match current {
+                    Some(current) => Id(conn.insert(song, current.pos as usize + 1)? as u32),
+                    None => conn.push(song)?,
+                };
+                conn.switch(id)
+            }
+            SongAction::AddToPlaylist(name) => conn.pl_push(name.as_str(), song),
+        }
+    }
+
+    pub fn done_text(&self) -> String {
+        match self {
+            SongAction::PlayNext => "✅ Song added, it plays next!".into(),
+            SongAction::Append => "✅ Song added to the end of the queue!".into(),
+            SongAction::PlayNow => "▶ Playing the song now!".into(),
+            SongAction::AddToPlaylist(name) => format!("✅ Song added to {name}!"),
+        }
+    }
+}
+
+/// Menu shown after tapping the search result in `slot`
+pub fn actions_keyboard(slot: u16) -> InlineKeyboardMarkup {
+    let song = |action| CallbackAction::Song { slot, action };
+    InlineKeyboardMarkup::new(vec![
+        vec![
+            song(SongAction::PlayNext).button("⏭ Play next"),
+            song(SongAction::Append).button("⬇ Add to end"),
+        ],
+        vec![
+            song(SongAction::PlayNow).button("▶ Play now"),
+            CallbackAction::PickPlaylist { slot }.button("📃 Add to playlist"),
+        ],
+    ])
+}
+
+/// Stored playlists a search result can be added to
+pub fn playlist_picker(slot: u16, playlists: &[Playlist]) -> InlineKeyboardMarkup {
+    let rows = playlists
+        .iter()
+        .filter_map(|x| {
+            let action = SongAction::AddToPlaylist(x.name.clone());
+            CallbackAction::Song { slot, action }.try_button(format!("📃 {}", x.name))
+        })
+        .map(|button| vec![button])
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(rows)
+}
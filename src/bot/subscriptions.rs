@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+use mpd::{Song, State};
+use teloxide::{ApiError, RequestError, prelude::*, types::ParseMode};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{presenter::SongView, rich_text::Markup};
+use crate::connection::MpdPool;
+
+/// Changes closer together than this (like skipping several songs in a row)
+/// only notify about the song playing at the end
+pub const NOTIFY_DEBOUNCE: Duration = Duration::from_secs(3);
+
+/// Chats that get a post whenever the song changes, kept in the DB
+#[derive(Clone)]
+pub struct Subscriptions {
+    tree: sled::Tree,
+}
+
+impl Subscriptions {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("subscribers")?,
+        })
+    }
+
+    /// Subscribe a chat or change its `silent` setting, returns whether it
+    /// was subscribed already
+    pub fn subscribe(&self, chat: ChatId, silent: bool) -> sled::Result<bool> {
+        Ok(self
+            .tree
+            .insert(chat.0.to_be_bytes(), &[silent as u8])?
+            .is_some())
+    }
+
+    /// Returns whether the chat was subscribed
+    pub fn unsubscribe(&self, chat: ChatId) -> sled::Result<bool> {
+        Ok(self.tree.remove(chat.0.to_be_bytes())?.is_some())
+    }
+
+    /// Subscribed chats, and whether they want silent notifications
+    pub fn list(&self) -> sled::Result<Vec<(ChatId, bool)>> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                let chat = i64::from_be_bytes(key.as_ref().try_into().unwrap_or_default());
+                Ok((ChatId(chat), value.first() == Some(&1)))
+            })
+            .collect()
+    }
+
+    /// Post the new song to every subscribed chat whenever it changes, meant
+    /// to be spawned once
+    pub async fn notify_loop(self, bot: Bot, mpd: MpdPool) {
+        let mut changes = mpd.watch();
+        let mut last = None;
+        loop {
+            if let Err(RecvError::Closed) = changes.recv().await {
+                return;
+            }
+            loop {
+                match tokio::time::timeout(NOTIFY_DEBOUNCE, changes.recv()).await {
+                    Ok(Err(RecvError::Closed)) => return,
+                    Ok(_) => continue,
+                    Err(_) => break,
+                }
+            }
+            let song = match mpd
+                .run(|conn| Ok((conn.status()?.state, conn.currentsong()?)))
+                .await
+            {
+                Ok((State::Play, Some(song))) => song,
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+            // Pausing and seeking wake the loop up too
+            let id = song.place.map(|x| x.id);
+            if last == Some((id, song.file.clone())) {
+                continue;
+            }
+            last = Some((id, song.file.clone()));
+            self.notify(&bot, song).await;
+        }
+    }
+
+    async fn notify(&self, bot: &Bot, song: Song) {
+        let subscribers = match self.list() {
+            Ok(subscribers) => subscribers,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        if subscribers.is_empty() {
+            return;
+        }
+        let text = format!(
+            "🎶 Now playing\n\n{}",
+            SongView::new(&song).markup(Markup::Html).full()
+        );
+        for (chat, silent) in subscribers {
+            match bot
+                .send_message(chat, &text)
+                .parse_mode(ParseMode::Html)
+                .disable_notification(silent)
+                .await
+            {
+                Ok(_) => {}
+                // The bot can't post there anymore, stop trying
+                Err(RequestError::Api(
+                    ApiError::BotBlocked
+                    | ApiError::BotKicked
+                    | ApiError::BotKickedFromSupergroup
+                    | ApiError::ChatNotFound
+                    | ApiError::UserDeactivated,
+                )) => {
+                    info!("Unsubscribing {chat}, the bot can't post there");
+                    if let Err(e) = self.unsubscribe(chat) {
+                        error!("{}", e);
+                    }
+                }
+                Err(e) => warn!("Can't notify {chat}: {}", e),
+            }
+        }
+    }
+}
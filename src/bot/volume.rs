@@ -0,0 +1,103 @@
+use teloxide::types::InlineKeyboardMarkup;
+
+use super::callback_data::CallbackAction;
+
+/// A volume change requested with `/volume` or the volume buttons
+#[derive(Clone, Copy, Debug)]
+pub enum VolumeChange {
+    Set(i8),
+    By(i8),
+}
+
+impl VolumeChange {
+    /// Parse `50`, `+10` or `-5`
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("mute") {
+            return Some(VolumeChange::Set(0));
+        }
+        let value = text.parse::<i8>().ok()?;
+        if text.starts_with(['+', '-']) {
+            Some(VolumeChange::By(value))
+        } else if (0..=100).contains(&value) {
+            Some(VolumeChange::Set(value))
+        } else {
+            None
+        }
+    }
+
+    /// Volume after the change, kept between 0 and 100
+    pub fn apply(self, current: i8) -> i8 {
+        match self {
+            VolumeChange::Set(x) => x,
+            VolumeChange::By(x) => current.saturating_add(x).clamp(0, 100),
+        }
+    }
+}
+
+pub fn volume_text(volume: i8) -> String {
+    let icon = match volume {
+        0 => "🔇",
+        1..=33 => "🔈",
+        34..=66 => "🔉",
+        _ => "🔊",
+    };
+    format!("{icon} Volume: {volume}%")
+}
+
+pub fn volume_keyboard() -> InlineKeyboardMarkup {
+    let buttons = [
+        ("−10", VolumeChange::By(-10)),
+        ("−5", VolumeChange::By(-5)),
+        ("🔇", VolumeChange::Set(0)),
+        ("+5", VolumeChange::By(5)),
+        ("+10", VolumeChange::By(10)),
+    ]
+    .into_iter()
+    .map(|(text, change)| CallbackAction::Volume(change).button(text))
+    .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(vec![buttons])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_changes() {
+        assert!(matches!(
+            VolumeChange::parse("50"),
+            Some(VolumeChange::Set(50))
+        ));
+        assert!(matches!(
+            VolumeChange::parse(" mute "),
+            Some(VolumeChange::Set(0))
+        ));
+        assert!(matches!(
+            VolumeChange::parse("+10"),
+            Some(VolumeChange::By(10))
+        ));
+        assert!(matches!(
+            VolumeChange::parse("-5"),
+            Some(VolumeChange::By(-5))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_changes() {
+        for text in ["", "loud", "101", "1000", "+200", "5%"] {
+            assert!(
+                VolumeChange::parse(text).is_none(),
+                "{text} shouldn't parse"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_volume_in_range() {
+        assert_eq!(VolumeChange::By(10).apply(95), 100);
+        assert_eq!(VolumeChange::By(-10).apply(5), 0);
+        assert_eq!(VolumeChange::By(127).apply(100), 100);
+        assert_eq!(VolumeChange::Set(30).apply(80), 30);
+    }
+}
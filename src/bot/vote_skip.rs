@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use mpd::{Id, error::Error as MpdError};
+use teloxide::types::{ChatId, InlineKeyboardMarkup, UserId};
+
+use super::callback_data::CallbackAction;
+use crate::connection::MpdClient;
+
+/// Users who wrote something in the chat this recently count towards percentage thresholds
+pub const ACTIVE_USER_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// How long votes for a song that just got skipped are ignored
+pub const CLOSED_VOTE_WINDOW: Duration = Duration::from_secs(30);
+
+/// How many votes are needed to skip a song
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    Votes(usize),
+    /// Percentage of the users recently active in the chat
+    Percent(u8),
+}
+
+/// Votes cast for the current song
+#[derive(Clone, Copy, Debug)]
+pub struct Tally {
+    pub votes: usize,
+    pub needed: usize,
+}
+
+impl Tally {
+    pub fn passed(&self) -> bool {
+        self.votes >= self.needed
+    }
+}
+
+#[derive(Default)]
+struct ChatVotes {
+    active: HashMap<UserId, Instant>,
+    song: Option<Id>,
+    voters: HashSet<UserId>,
+    /// When the vote on `song` passed and skipped it
+    closed: Option<Instant>,
+}
+
+impl ChatVotes {
+    /// Whether the vote on `song` passed recently. Queue ids come back with
+    /// repeat on, so a closed vote doesn't stay closed forever.
+    fn is_closed(&self, song: Id) -> bool {
+        self.song == Some(song)
+            && self
+                .closed
+                .is_some_and(|x| x.elapsed() < CLOSED_VOTE_WINDOW)
+    }
+}
+
+/// Vote-to-skip state for group chats, enabled by setting `TMPC_SKIP_VOTES` to
+/// either a number of votes (`3`) or a share of recently active users (`50%`).
+#[derive(Clone, Default)]
+pub struct SkipVotes {
+    threshold: Option<Threshold>,
+    chats: Arc<Mutex<HashMap<ChatId, ChatVotes>>>,
+}
+
+impl SkipVotes {
+    pub fn from_env() -> Self {
+        let threshold = env::var("TMPC_SKIP_VOTES").ok().and_then(|x| {
+            let parsed = match x.trim().strip_suffix('%') {
+                Some(percent) => percent
+                    .trim()
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|x| (1..=100).contains(x))
+                    .map(Threshold::Percent),
+                None => x
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .map(Threshold::Votes),
+            };
+            if parsed.is_none() {
+                warn!("Ignoring invalid TMPC_SKIP_VOTES: {x}");
+            }
+            parsed
+        });
+        if let Some(threshold) = threshold {
+            info!("Vote to skip enabled: {:?}", threshold);
+        }
+
+        Self {
+            threshold,
+            chats: Default::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.threshold.is_some()
+    }
+
+    /// Remember that `user` is taking part in the chat
+    pub fn seen(&self, chat: ChatId, user: UserId) {
+        if !self.enabled() {
+            return;
+        }
+        let mut chats = self.chats.lock().unwrap();
+        let votes = chats.entry(chat).or_default();
+        let now = Instant::now();
+        votes
+            .active
+            .retain(|_, last| now.duration_since(*last) < ACTIVE_USER_WINDOW);
+        votes.active.insert(user, now);
+    }
+
+    /// Count `user`'s vote to skip `song`, starting over if the song changed
+    /// since the last vote
+    pub fn vote(&self, chat: ChatId, user: UserId, song: Id) -> Tally {
+        self.seen(chat, user);
+        let mut chats = self.chats.lock().unwrap();
+        let votes = chats.entry(chat).or_default();
+        if votes.song != Some(song) || (votes.closed.is_some() && !votes.is_closed(song)) {
+            votes.song = Some(song);
+            votes.voters.clear();
+            votes.closed = None;
+        }
+        votes.voters.insert(user);
+
+        let needed = match self.threshold {
+            Some(Threshold::Votes(n)) => n,
+            Some(Threshold::Percent(p)) => (votes.active.len() * p as usize).div_ceil(100),
+            None => 1,
+        };
+        Tally {
+            votes: votes.voters.len(),
+            needed: needed.max(1),
+        }
+    }
+
+    /// Close the vote on `song` before skipping it. Only the first caller
+    /// gets `true`, so votes arriving together don't skip several songs.
+    pub fn close(&self, chat: ChatId, song: Id) -> bool {
+        let mut chats = self.chats.lock().unwrap();
+        let votes = chats.entry(chat).or_default();
+        if votes.is_closed(song) {
+            return false;
+        }
+        votes.song = Some(song);
+        votes.voters.clear();
+        votes.closed = Some(Instant::now());
+        true
+    }
+}
+
+/// Skip `song` if it's still the one playing, returning whether it was
+pub fn skip(conn: &mut MpdClient, song: Id) -> Result<bool, MpdError> {
+    let current = conn.currentsong()?.and_then(|x| x.place).map(|x| x.id);
+    if current != Some(song) {
+        return Ok(false);
+    }
+    conn.next()?;
+    Ok(true)
+}
+
+pub fn vote_keyboard(song: Id, tally: Tally) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        CallbackAction::VoteSkip { song }
+            .button(format!("👍 Skip ({}/{})", tally.votes, tally.needed)),
+        CallbackAction::ForceSkip { song }.button("⏭ Force skip"),
+    ]])
+}
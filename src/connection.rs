@@ -0,0 +1,442 @@
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    ops::{Deref, DerefMut},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use mpd::{
+    Client, Idle, Subsystem,
+    error::{Error as MpdError, ProtoError, ServerError},
+};
+use tokio::{sync::broadcast, task};
+
+pub const DEFAULT_MPD_PORT: u16 = 6600;
+/// How many idle connections the pool keeps open at most
+pub const MAX_IDLE_CONNECTIONS: usize = 4;
+/// How long the watcher of [`MpdPool::watch`] waits before connecting again after an error
+pub const WATCH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub type MpdClient = Client<MpdStream>;
+
+/// Where the MPD server is listening
+#[derive(Clone, Debug)]
+pub enum MpdAddress {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+/// Everything needed to open a connection to MPD, read from the environment:
+///
+/// - `TMPC_MPD_SOCKET` — path to MPD's unix socket
+/// - `TMPC_MPD_HOST`, `TMPC_MPD_PORT` — TCP address, used when no socket is set
+/// - `TMPC_MPD_PASSWORD` — sent with the `password` command after connecting
+///
+/// Without any of those, `~/.config/mpd/socket` is used if it exists, and
+/// `127.0.0.1:6600` otherwise.
+#[derive(Clone, Debug)]
+pub struct MpdConfig {
+    pub address: MpdAddress,
+    pub password: Option<String>,
+}
+
+impl MpdConfig {
+    pub fn from_env() -> Self {
+        let address = if let Ok(path) = env::var("TMPC_MPD_SOCKET") {
+            MpdAddress::Unix(path.into())
+        } else if let Ok(host) = env::var("TMPC_MPD_HOST") {
+            let port = env::var("TMPC_MPD_PORT")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(DEFAULT_MPD_PORT);
+            MpdAddress::Tcp(host, port)
+        } else {
+            match default_socket_path() {
+                Some(path) => MpdAddress::Unix(path),
+                None => MpdAddress::Tcp("127.0.0.1".into(), DEFAULT_MPD_PORT),
+            }
+        };
+        let password = env::var("TMPC_MPD_PASSWORD").ok().filter(|x| !x.is_empty());
+        info!("Using mpd at {:?}", address);
+
+        Self { address, password }
+    }
+
+    fn open_stream(&self) -> io::Result<MpdStream> {
+        Ok(match &self.address {
+            MpdAddress::Unix(path) => MpdStream::Unix(UnixStream::connect(path)?),
+            MpdAddress::Tcp(host, port) => {
+                MpdStream::Tcp(TcpStream::connect((host.as_str(), *port))?)
+            }
+        })
+    }
+
+    /// Open a new connection and authenticate if a password is configured
+    pub fn connect(&self) -> Result<MpdClient, MpdError> {
+        let mut client = Client::new(self.open_stream()?)?;
+        if let Some(password) = &self.password {
+            client.login(password)?;
+        }
+        Ok(client)
+    }
+
+    /// Open a short-lived connection for raw commands, past the banner and
+    /// the password
+    fn open_raw(&self) -> Result<(MpdStream, BufReader<MpdStream>), MpdError> {
+        let mut stream = self.open_stream()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut banner = String::new();
+        reader.read_line(&mut banner)?;
+        if !banner.starts_with("OK MPD ") {
+            return Err(ProtoError::BadBanner.into());
+        }
+        if let Some(password) = &self.password {
+            writeln!(stream, "password {}", quote(password)?)?;
+            read_response(&mut reader)?;
+        }
+        Ok((stream, reader))
+    }
+
+    /// Run a command the mpd crate doesn't cover (like `single oneshot`) on a
+    /// short-lived connection and return the key/value pairs of the response
+    pub fn raw_command(&self, command: &str) -> Result<Vec<(String, String)>, MpdError> {
+        let (mut stream, mut reader) = self.open_raw()?;
+        writeln!(stream, "{command}")?;
+        let res = read_response(&mut reader);
+        let _ = writeln!(stream, "close");
+        res
+    }
+
+    /// Run a command answering with binary data in chunks, like `albumart` or
+    /// `readpicture`, asking for the next chunk until all of it arrived.
+    /// `None` when the response has no data.
+    pub fn binary_command(&self, command: &str) -> Result<Option<Vec<u8>>, MpdError> {
+        let (mut stream, mut reader) = self.open_raw()?;
+        let mut data = Vec::new();
+        loop {
+            writeln!(stream, "{command} {}", data.len())?;
+            let mut size = None;
+            let mut chunk = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(ProtoError::NotOk.into());
+                }
+                let line = line.trim_end_matches('\n');
+                if line == "OK" {
+                    break;
+                }
+                if line.starts_with("ACK ") {
+                    return Err(MpdError::Server(line.parse::<ServerError>()?));
+                }
+                match line.split_once(": ") {
+                    Some(("size", val)) => size = val.parse::<usize>().ok(),
+                    Some(("binary", val)) => {
+                        chunk = vec![0; val.parse().map_err(|_| ProtoError::NotPair)?];
+                        reader.read_exact(&mut chunk)?;
+                        // The data is followed by a newline
+                        reader.read_line(&mut String::new())?;
+                    }
+                    _ => {}
+                }
+            }
+            let Some(size) = size else {
+                let _ = writeln!(stream, "close");
+                return Ok(None);
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend(chunk);
+            if data.len() >= size {
+                break;
+            }
+        }
+        let _ = writeln!(stream, "close");
+        Ok(Some(data))
+    }
+}
+
+/// Quote an argument of a raw command. Arguments with control characters
+/// are refused, a newline would end the command and start another one.
+pub fn quote(arg: &str) -> Result<String, MpdError> {
+    if arg.chars().any(char::is_control) {
+        return Err(MpdError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "control character in a command argument",
+        )));
+    }
+    Ok(format!(
+        "\"{}\"",
+        arg.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+fn read_response(reader: &mut impl BufRead) -> Result<Vec<(String, String)>, MpdError> {
+    let mut pairs = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ProtoError::NotOk.into());
+        }
+        let line = line.trim_end_matches('\n');
+        if line == "OK" {
+            return Ok(pairs);
+        }
+        if line.starts_with("ACK ") {
+            return Err(MpdError::Server(line.parse::<ServerError>()?));
+        }
+        if let Some((key, val)) = line.split_once(": ") {
+            pairs.push((key.to_string(), val.to_string()));
+        }
+    }
+}
+
+fn default_socket_path() -> Option<PathBuf> {
+    let config_dir = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|x| PathBuf::from(x).join(".config")))
+        .ok()?;
+    let path = config_dir.join("mpd").join("socket");
+    path.exists().then_some(path)
+}
+
+/// Shared set of MPD connections, handed out to handlers and returned when
+/// they're done so bursts of updates don't open a socket each.
+#[derive(Clone)]
+pub struct MpdPool {
+    config: MpdConfig,
+    idle: Arc<Mutex<Vec<MpdClient>>>,
+    watcher: Arc<OnceLock<broadcast::Sender<Subsystem>>>,
+}
+
+impl MpdPool {
+    pub fn new(config: MpdConfig) -> Self {
+        Self {
+            config,
+            idle: Arc::new(Mutex::new(Vec::with_capacity(MAX_IDLE_CONNECTIONS))),
+            watcher: Default::default(),
+        }
+    }
+
+    /// Borrow a connection, reusing an idle one if it still answers `ping`
+    /// and connecting again otherwise
+    pub fn get(&self) -> Result<PooledClient, MpdError> {
+        loop {
+            let Some(mut client) = self.idle.lock().unwrap().pop() else {
+                break;
+            };
+            match client.ping() {
+                Ok(_) => return Ok(PooledClient::new(client, self.clone())),
+                Err(e) => debug!("Dropping stale mpd connection: {}", e),
+            }
+        }
+        Ok(PooledClient::new(self.config.connect()?, self.clone()))
+    }
+
+    /// Run `f` on a pooled connection. `get` already replaced connections
+    /// that went stale, and a command that failed halfway may have been
+    /// applied by MPD, so nothing is retried here.
+    pub fn with<T>(
+        &self,
+        f: impl FnOnce(&mut MpdClient) -> Result<T, MpdError>,
+    ) -> Result<T, MpdError> {
+        let mut client = self.get()?;
+        let res = f(&mut client);
+        match &res {
+            // MPD answered with an error, the connection is still in sync
+            Ok(_) | Err(MpdError::Server(_)) => {}
+            // Unread lines of the response may be left on the socket
+            Err(e) => {
+                warn!("Dropping mpd connection after an error: {}", e);
+                client.discard();
+            }
+        }
+        res
+    }
+
+    /// Same as [`MpdPool::with`], but on tokio's blocking thread pool so the
+    /// socket I/O doesn't stall the dispatcher
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut MpdClient) -> Result<T, MpdError> + Send + 'static,
+    ) -> Result<T, MpdError> {
+        let pool = self.clone();
+        task::spawn_blocking(move || pool.with(f))
+            .await
+            .map_err(|e| MpdError::Io(io::Error::other(e)))?
+    }
+
+    /// Same as [`MpdConfig::raw_command`], on tokio's blocking thread pool
+    pub async fn raw_command(&self, command: String) -> Result<Vec<(String, String)>, MpdError> {
+        let config = self.config.clone();
+        task::spawn_blocking(move || config.raw_command(&command))
+            .await
+            .map_err(|e| MpdError::Io(io::Error::other(e)))?
+    }
+
+    /// Same as [`MpdConfig::binary_command`], on tokio's blocking thread pool
+    pub async fn binary_command(&self, command: String) -> Result<Option<Vec<u8>>, MpdError> {
+        let config = self.config.clone();
+        task::spawn_blocking(move || config.binary_command(&command))
+            .await
+            .map_err(|e| MpdError::Io(io::Error::other(e)))?
+    }
+
+    /// Changes of the player, like songs starting or playback pausing.
+    /// `idle` blocks its connection, so a single thread and connection wait
+    /// for them, started on first use and shared by every receiver.
+    pub fn watch(&self) -> broadcast::Receiver<Subsystem> {
+        self.watcher
+            .get_or_init(|| {
+                let (tx, _) = broadcast::channel(16);
+                let config = self.config.clone();
+                let sender = tx.clone();
+                thread::spawn(move || watch_player(config, sender));
+                tx
+            })
+            .subscribe()
+    }
+
+    fn put_back(&self, client: MpdClient) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(client);
+        }
+    }
+}
+
+fn watch_player(config: MpdConfig, tx: broadcast::Sender<Subsystem>) {
+    loop {
+        let mut client = match config.connect() {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Can't connect to watch mpd: {}", e);
+                thread::sleep(WATCH_RETRY_DELAY);
+                continue;
+            }
+        };
+        loop {
+            match client.wait(&[Subsystem::Player]) {
+                Ok(changes) => {
+                    for change in changes {
+                        // Nobody listening right now is fine
+                        let _ = tx.send(change);
+                    }
+                }
+                Err(e) => {
+                    warn!("Lost mpd connection while watching: {}", e);
+                    thread::sleep(WATCH_RETRY_DELAY);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A connection borrowed from [`MpdPool`], returned to it on drop
+pub struct PooledClient {
+    client: Option<MpdClient>,
+    pool: MpdPool,
+}
+
+impl PooledClient {
+    fn new(client: MpdClient, pool: MpdPool) -> Self {
+        Self {
+            client: Some(client),
+            pool,
+        }
+    }
+
+    /// Close the connection instead of returning it to the pool
+    pub fn discard(&mut self) {
+        self.client = None;
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = MpdClient;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("connection was discarded")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("connection was discarded")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.put_back(client);
+        }
+    }
+}
+
+/// A socket to MPD, either local or over the network
+#[derive(Debug)]
+pub enum MpdStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl MpdStream {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            MpdStream::Unix(s) => MpdStream::Unix(s.try_clone()?),
+            MpdStream::Tcp(s) => MpdStream::Tcp(s.try_clone()?),
+        })
+    }
+}
+
+impl Read for MpdStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MpdStream::Unix(s) => s.read(buf),
+            MpdStream::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for MpdStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MpdStream::Unix(s) => s.write(buf),
+            MpdStream::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MpdStream::Unix(s) => s.flush(),
+            MpdStream::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_arguments() {
+        assert_eq!(quote("Music/AC DC").unwrap(), r#""Music/AC DC""#);
+        assert_eq!(quote(r#"a "b" \c"#).unwrap(), r#""a \"b\" \\c""#);
+    }
+
+    #[test]
+    fn refuses_control_characters() {
+        assert!(quote("foo\nclear").is_err());
+        assert!(quote("foo\r\nclear").is_err());
+        assert!(quote("foo\0").is_err());
+    }
+}
@@ -1,13 +1,18 @@
 use std::env;
 
-use bot::{BotState, schema};
+use bot::{
+    BotState, access::AccessList, album_art::AlbumArt, callback_state::CallbackStore,
+    now_playing::LiveMessages, rate_limit::RateLimiter, schema, subscriptions::Subscriptions,
+    vote_skip::SkipVotes,
+};
+use connection::{MpdConfig, MpdPool};
 use log::error;
 #[cfg(feature = "local")]
 use reqwest::Url;
 use teloxide::{dispatching::dialogue::InMemStorage, prelude::*};
 mod bot;
+mod connection;
 
-pub const MPD_SOCKET_PATH: &str = "/home/pasta/.config/mpd/socket";
 pub const REACTION_EMOJI: &str = "🍾";
 
 #[tokio::main]
@@ -19,11 +24,57 @@ async fn main() {
         error!("No token defined");
         return;
     };
+    let mpd = MpdPool::new(MpdConfig::from_env());
+    let acl = AccessList::from_env();
+    let db = match sled::open("DB") {
+        Ok(db) => db,
+        Err(e) => {
+            error!("Can't open DB: {}", e);
+            return;
+        }
+    };
+    let limiter = RateLimiter::from_env(&db);
+    let votes = SkipVotes::from_env();
+    let live = LiveMessages::from_env();
+    let store = match CallbackStore::open(&db) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Can't open callback state: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(store.clone().purge_loop());
+    let subs = match Subscriptions::open(&db) {
+        Ok(subs) => subs,
+        Err(e) => {
+            error!("Can't open subscriptions: {}", e);
+            return;
+        }
+    };
+    let art = match AlbumArt::open(&db) {
+        Ok(art) => art,
+        Err(e) => {
+            error!("Can't open album art cache: {}", e);
+            return;
+        }
+    };
     let bot = Bot::new(token);
     #[cfg(feature = "local")]
     let bot = bot.set_api_url(Url::parse("http://127.0.0.1:8080").unwrap());
+    tokio::spawn(subs.clone().notify_loop(bot.clone(), mpd.clone()));
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![InMemStorage::<BotState>::new()])
+        .dependencies(dptree::deps![
+            InMemStorage::<BotState>::new(),
+            mpd,
+            acl,
+            limiter,
+            votes,
+            store,
+            live,
+            subs,
+            art,
+            db
+        ])
         .enable_ctrlc_handler()
         .build()
         .dispatch()